@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use clap::ValueEnum;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::handshake;
+use crate::protocol::{DecodeError, Frame};
+use crate::transport::ClockTransport;
+
+const TRIAL_TIMEOUT: Duration = Duration::from_millis(500);
+const TRIAL_PAUSE: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Method {
+    /// Time a byte coming back, from echoing firmware or a TX-RX loopback jumper
+    Echo,
+    /// Time the answer to a time query
+    Query,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Calibration {
+    // One-way delay from writing a byte to the device acting on it, taken as
+    // half the round trip.
+    pub median_us: u64,
+    // Standard deviation over the trials.
+    pub jitter_us: u64,
+    pub trials: usize,
+    pub method: Method,
+    pub measured_at: String,
+}
+
+impl Calibration {
+    pub fn latency(&self) -> Duration {
+        Duration::from_micros(self.median_us)
+    }
+}
+
+// Calibrations keyed by device identity, kept in a TOML file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CalibrationDb {
+    #[serde(default, rename = "device")]
+    devices: BTreeMap<String, Calibration>,
+}
+
+impl CalibrationDb {
+    // A missing file is an empty database.
+    pub fn load(path: &Path) -> Result<CalibrationDb, Box<dyn Error>> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(CalibrationDb::default()),
+            Err(e) => {
+                return Err(format!("Failed to read calibrations {}: {}", path.display(), e).into())
+            }
+        };
+        let db = toml::from_str(&text)
+            .map_err(|e| format!("Failed to parse calibrations {}: {}", path.display(), e))?;
+        Ok(db)
+    }
+
+    // Written to a temporary file first so a crash never leaves half a file.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("toml.tmp");
+        fs::write(&tmp, toml::to_string(self)?)?;
+        fs::rename(&tmp, path)
+            .map_err(|e| format!("Failed to write calibrations {}: {}", path.display(), e))?;
+        Ok(())
+    }
+
+    pub fn get(&self, identity: &str) -> Option<&Calibration> {
+        self.devices.get(identity)
+    }
+
+    pub fn insert(&mut self, identity: &str, calibration: Calibration) {
+        self.devices.insert(identity.to_string(), calibration);
+    }
+}
+
+pub fn default_path() -> PathBuf {
+    let data_dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+    };
+    data_dir
+        .unwrap_or_else(env::temp_dir)
+        .join("mytimesync")
+        .join("calibration.toml")
+}
+
+// Round trip of one request, up to the complete answer. An answer longer
+// than the request spends the extra characters on the line, which is not
+// link latency, so their time is left out.
+fn trial(
+    transport: &mut dyn ClockTransport,
+    method: Method,
+    byte: u8,
+) -> Result<Duration, Box<dyn Error>> {
+    handshake::drain(transport)?;
+    let request = match method {
+        Method::Echo => vec![byte],
+        Method::Query => Frame::QueryTime.encode(),
+    };
+    let started = Instant::now();
+    transport.send(&request)?;
+    let mut reply = Vec::new();
+    let mut buf = [0u8; 64];
+    loop {
+        let remaining = TRIAL_TIMEOUT.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            return Err(format!("No answer within {:?}, got {:02x?}", TRIAL_TIMEOUT, reply).into());
+        }
+        let len = transport.receive(&mut buf, remaining)?;
+        reply.extend_from_slice(&buf[..len]);
+        let answer_len = match method {
+            Method::Echo => reply.contains(&byte).then_some(1),
+            Method::Query => match Frame::decode(&reply) {
+                Ok((Frame::TimeReport { .. }, len)) => Some(len),
+                Err(DecodeError::Truncated { .. }) => None,
+                Ok((frame, _)) => return Err(format!("Unexpected reply {}", frame).into()),
+                Err(e) => return Err(format!("Invalid reply {:02x?}: {}", reply, e).into()),
+            },
+        };
+        if let Some(answer_len) = answer_len {
+            let extra = answer_len.saturating_sub(request.len()) as u32;
+            return Ok(started
+                .elapsed()
+                .saturating_sub(transport.char_time() * extra));
+        }
+    }
+}
+
+pub fn measure(
+    transport: &mut dyn ClockTransport,
+    method: Method,
+    byte: u8,
+    trials: usize,
+) -> Result<Calibration, Box<dyn Error>> {
+    let mut one_way_us = Vec::with_capacity(trials);
+    for i in 0..trials {
+        match trial(transport, method, byte) {
+            Ok(round_trip) => {
+                debug!("Trial {}: round trip {} µs", i + 1, round_trip.as_micros());
+                one_way_us.push(round_trip.as_micros() as f64 / 2.0);
+            }
+            Err(e) => warn!("Trial {} failed: {}", i + 1, e),
+        }
+        thread::sleep(TRIAL_PAUSE);
+    }
+    // A lost answer now and then is no reason to throw the rest away.
+    if one_way_us.is_empty() || one_way_us.len() * 2 < trials {
+        return Err(format!("Only {} of {} trials succeeded", one_way_us.len(), trials).into());
+    }
+    one_way_us.sort_by(|a, b| a.total_cmp(b));
+    let mid = one_way_us.len() / 2;
+    let median = if one_way_us.len() % 2 == 0 {
+        (one_way_us[mid - 1] + one_way_us[mid]) / 2.0
+    } else {
+        one_way_us[mid]
+    };
+    let mean = one_way_us.iter().sum::<f64>() / one_way_us.len() as f64;
+    let variance =
+        one_way_us.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / one_way_us.len() as f64;
+    Ok(Calibration {
+        median_us: median.round() as u64,
+        jitter_us: variance.sqrt().round() as u64,
+        trials: one_way_us.len(),
+        method,
+        measured_at: Local::now().to_rfc3339(),
+    })
+}
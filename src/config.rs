@@ -0,0 +1,132 @@
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use serde::Deserialize;
+
+use crate::calibration::{self, CalibrationDb};
+use crate::device::{DeviceConfig, Target};
+use crate::discovery::PortInfo;
+use crate::profile::{DeviceProfile, DEFAULT_PROFILE};
+use crate::rules::MatchRule;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    #[serde(rename = "rule")]
+    pub rules: Vec<MatchRule>,
+    // Keyed by alias.
+    #[serde(rename = "device")]
+    pub devices: BTreeMap<String, DeviceConfig>,
+    #[serde(rename = "profile")]
+    pub profiles: BTreeMap<String, DeviceProfile>,
+    // Directories of NAME.toml profile files; profiles defined inline take
+    // precedence.
+    pub profile_dirs: Vec<PathBuf>,
+    // Where `calibrate` stores measured latencies.
+    pub calibration_db: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+        let mut config: Config = toml::from_str(&text)
+            .map_err(|e| format!("Failed to parse config {}: {}", path.display(), e))?;
+        for dir in &config.profile_dirs.clone() {
+            // Relative directories are relative to the config file.
+            let dir = path.parent().unwrap_or(Path::new(".")).join(dir);
+            config.load_profile_dir(&dir)?;
+        }
+        for (name, profile) in &config.profiles {
+            profile
+                .validate()
+                .map_err(|e| format!("Invalid profile {}: {}", name, e))?;
+        }
+        for (alias, device) in &config.devices {
+            device
+                .validate()
+                .map_err(|e| format!("Invalid device {}: {}", alias, e))?;
+        }
+        Ok(config)
+    }
+
+    fn load_profile_dir(&mut self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        let entries = fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read profile directory {}: {}", dir.display(), e))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("toml") {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            if let Entry::Vacant(entry) = self.profiles.entry(name) {
+                debug!("Loading profile {} from {}", entry.key(), path.display());
+                entry.insert(DeviceProfile::load(&path)?);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn rules(&self) -> Vec<MatchRule> {
+        if self.rules.is_empty() {
+            MatchRule::defaults()
+        } else {
+            self.rules.clone()
+        }
+    }
+
+    pub fn profile(&self, name: Option<&str>) -> Result<DeviceProfile, Box<dyn Error>> {
+        match (name, self.profiles.get(name.unwrap_or(DEFAULT_PROFILE))) {
+            (_, Some(profile)) => Ok(profile.clone()),
+            (None, None) => Ok(DeviceProfile::default()),
+            (Some(name), None) => Err(format!("Unknown device profile {}", name).into()),
+        }
+    }
+
+    pub fn calibration_path(&self) -> PathBuf {
+        self.calibration_db
+            .clone()
+            .unwrap_or_else(calibration::default_path)
+    }
+
+    pub fn target(&self, port: &PortInfo) -> Result<Target, Box<dyn Error>> {
+        let device = match self.devices.iter().find(|(_, device)| device.matches(port)) {
+            Some((alias, device)) => {
+                debug!("{} is device {}", port.name, alias);
+                device.clone()
+            }
+            None => DeviceConfig::default(),
+        };
+        // A profile named for the device wins over one picked by model.
+        let mut model_profiles = BTreeMap::new();
+        if device.identify && device.profile.is_none() {
+            for profile in self.profiles.values() {
+                if let Some(model) = profile.model {
+                    model_profiles
+                        .entry(model)
+                        .or_insert_with(|| profile.clone());
+                }
+            }
+        }
+        // Read for every target so a running watcher sees new calibrations.
+        let identity = port.identity();
+        let calibration = CalibrationDb::load(&self.calibration_path())?
+            .get(&identity)
+            .cloned();
+        Ok(Target {
+            name: port.name.clone(),
+            identity,
+            profile: self.profile(device.profile.as_deref())?,
+            device,
+            model_profiles,
+            calibration,
+        })
+    }
+}
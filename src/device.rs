@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serialport::{DataBits, FlowControl, Parity, StopBits};
+
+use crate::calibration::Calibration;
+use crate::discovery::PortInfo;
+use crate::profile::DeviceProfile;
+use crate::transport::PortSettings;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParityConfig {
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowControlConfig {
+    None,
+    Software,
+    Hardware,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProbeConfig {
+    pub baud_rates: Vec<u32>,
+    pub request: Vec<u8>,
+    #[serde(default)]
+    pub response_prefix: Vec<u8>,
+    #[serde(default = "default_probe_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_probe_timeout_ms() -> u64 {
+    200
+}
+
+impl ProbeConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+// How to tell that a device is ready after opening the port, for boards
+// that reset when it is opened.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum ReadyConfig {
+    // Wait until the device prints `banner`.
+    Banner {
+        banner: String,
+        #[serde(default = "default_ready_timeout_ms")]
+        timeout_ms: u64,
+    },
+    // Just give it this long.
+    Delay {
+        delay_ms: u64,
+    },
+}
+
+fn default_ready_timeout_ms() -> u64 {
+    5000
+}
+
+// A device is identified by any combination of port name, USB serial number
+// and physical location; every field given has to match.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    pub port: Option<String>,
+    pub serial_number: Option<String>,
+    pub location: Option<String>,
+    pub profile: Option<String>,
+    pub baud_rate: Option<u32>,
+    pub data_bits: Option<u8>,
+    pub parity: Option<ParityConfig>,
+    pub stop_bits: Option<u8>,
+    pub flow_control: Option<FlowControlConfig>,
+    pub probe: Option<ProbeConfig>,
+    pub dtr: Option<bool>,
+    pub rts: Option<bool>,
+    // Latency timer of the USB serial adapter, added to the time the UART
+    // takes for the commit byte.
+    pub usb_latency_us: Option<u64>,
+    pub ready: Option<ReadyConfig>,
+    // Ask the device what it is once the port is open.
+    #[serde(default)]
+    pub identify: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub name: String,
+    pub identity: String,
+    pub device: DeviceConfig,
+    pub profile: DeviceProfile,
+    // Profiles to switch to when identification reports their model.
+    pub model_profiles: BTreeMap<u16, DeviceProfile>,
+    pub calibration: Option<Calibration>,
+}
+
+impl DeviceConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(ReadyConfig::Banner { banner, .. }) = &self.ready {
+            if banner.is_empty() {
+                return Err("ready banner is empty".into());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, port: &PortInfo) -> bool {
+        let location = self
+            .location
+            .as_deref()
+            .map(|l| l.trim_start_matches("/dev/serial/by-path/"));
+        let fields = [
+            (self.port.as_deref(), Some(port.name.as_str())),
+            (self.serial_number.as_deref(), port.serial_number.as_deref()),
+            (location, port.location.as_deref()),
+        ];
+        fields.iter().any(|(expected, _)| expected.is_some())
+            && fields
+                .iter()
+                .all(|(expected, actual)| expected.is_none() || expected == actual)
+    }
+
+    // Device settings override the profile's, which override the defaults.
+    pub fn port_settings(&self, profile: &DeviceProfile) -> Result<PortSettings, Box<dyn Error>> {
+        let defaults = PortSettings::default();
+        let data_bits = match self.data_bits {
+            None => defaults.data_bits,
+            Some(5) => DataBits::Five,
+            Some(6) => DataBits::Six,
+            Some(7) => DataBits::Seven,
+            Some(8) => DataBits::Eight,
+            Some(other) => return Err(format!("Unsupported data bits {}", other).into()),
+        };
+        let stop_bits = match self.stop_bits {
+            None => defaults.stop_bits,
+            Some(1) => StopBits::One,
+            Some(2) => StopBits::Two,
+            Some(other) => return Err(format!("Unsupported stop bits {}", other).into()),
+        };
+        let parity = match self.parity {
+            None => defaults.parity,
+            Some(ParityConfig::None) => Parity::None,
+            Some(ParityConfig::Odd) => Parity::Odd,
+            Some(ParityConfig::Even) => Parity::Even,
+        };
+        let flow_control = match self.flow_control {
+            None => defaults.flow_control,
+            Some(FlowControlConfig::None) => FlowControl::None,
+            Some(FlowControlConfig::Software) => FlowControl::Software,
+            Some(FlowControlConfig::Hardware) => FlowControl::Hardware,
+        };
+        Ok(PortSettings {
+            baud_rate: self
+                .baud_rate
+                .or(profile.baud_rate)
+                .unwrap_or(defaults.baud_rate),
+            data_bits,
+            parity,
+            stop_bits,
+            flow_control,
+            dtr: self.dtr,
+            rts: self.rts,
+            usb_latency: self
+                .usb_latency_us
+                .map_or(defaults.usb_latency, Duration::from_micros),
+        })
+    }
+}
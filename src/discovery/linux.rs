@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{PortDiscovery, PortInfo};
+
+const SYS_CLASS_TTY: &str = "/sys/class/tty";
+const DEV_SERIAL_BY_PATH: &str = "/dev/serial/by-path";
+
+pub struct SysfsDiscovery;
+
+fn read_attr(dir: &Path, name: &str) -> Option<String> {
+    let value = fs::read_to_string(dir.join(name)).ok()?;
+    let value = value.trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+fn read_hex_attr(dir: &Path, name: &str) -> Option<u16> {
+    u16::from_str_radix(&read_attr(dir, name)?, 16).ok()
+}
+
+// Maps the resolved /dev node of every symlink in `dir` to the link name.
+fn read_links(dir: &str) -> HashMap<PathBuf, String> {
+    let mut links = HashMap::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return links,
+    };
+    for entry in entries.flatten() {
+        if let Ok(target) = fs::canonicalize(entry.path()) {
+            links.insert(target, entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    links
+}
+
+fn find_usb_device(device: &Path) -> Option<&Path> {
+    device
+        .ancestors()
+        .find(|dir| dir.join("idVendor").is_file())
+}
+
+impl PortDiscovery for SysfsDiscovery {
+    fn list_ports(&self) -> Result<Vec<PortInfo>, Box<dyn Error>> {
+        let by_path = read_links(DEV_SERIAL_BY_PATH);
+        let mut ports = Vec::new();
+        for entry in fs::read_dir(SYS_CLASS_TTY)? {
+            let entry = entry?;
+            // Virtual terminals have no backing device.
+            let device = match fs::canonicalize(entry.path().join("device")) {
+                Ok(device) => device,
+                Err(_) => continue,
+            };
+            let usb = match find_usb_device(&device) {
+                Some(usb) => usb,
+                None => continue,
+            };
+            let dev_node = Path::new("/dev").join(entry.file_name());
+            let location = by_path
+                .get(&dev_node)
+                .cloned()
+                .or_else(|| usb.file_name().map(|n| n.to_string_lossy().into_owned()));
+            ports.push(PortInfo {
+                name: dev_node.to_string_lossy().into_owned(),
+                vid: read_hex_attr(usb, "idVendor"),
+                pid: read_hex_attr(usb, "idProduct"),
+                manufacturer: read_attr(usb, "manufacturer"),
+                product: read_attr(usb, "product"),
+                serial_number: read_attr(usb, "serial"),
+                location,
+            });
+        }
+        ports.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(ports)
+    }
+}
@@ -0,0 +1,52 @@
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(windows)]
+mod windows;
+
+use std::error::Error;
+
+#[derive(Debug, Clone, Default)]
+pub struct PortInfo {
+    pub name: String,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+    pub location: Option<String>,
+}
+
+impl PortInfo {
+    // Stable name for the physical device, independent of enumeration order.
+    pub fn identity(&self) -> String {
+        self.serial_number
+            .clone()
+            .or_else(|| self.location.clone())
+            .unwrap_or_else(|| self.name.clone())
+    }
+}
+
+pub trait PortDiscovery {
+    fn list_ports(&self) -> Result<Vec<PortInfo>, Box<dyn Error>>;
+}
+
+#[cfg(target_os = "linux")]
+pub fn platform_discovery() -> Box<dyn PortDiscovery> {
+    Box::new(linux::SysfsDiscovery)
+}
+
+#[cfg(windows)]
+pub fn platform_discovery() -> Box<dyn PortDiscovery> {
+    Box::new(windows::WmiDiscovery)
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn platform_discovery() -> Box<dyn PortDiscovery> {
+    struct Unsupported;
+    impl PortDiscovery for Unsupported {
+        fn list_ports(&self) -> Result<Vec<PortInfo>, Box<dyn Error>> {
+            Err("Serial port discovery is not supported on this platform".into())
+        }
+    }
+    Box::new(Unsupported)
+}
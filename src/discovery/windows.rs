@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use wmi::{COMLibrary, Variant, WMIConnection};
+
+use super::{PortDiscovery, PortInfo};
+
+pub struct WmiDiscovery;
+
+fn get_string<'a>(result: &'a HashMap<String, Variant>, key: &str) -> Option<&'a str> {
+    match result.get(key) {
+        Some(Variant::String(value)) => Some(value),
+        _ => None,
+    }
+}
+
+impl PortDiscovery for WmiDiscovery {
+    fn list_ports(&self) -> Result<Vec<PortInfo>, Box<dyn Error>> {
+        lazy_static! {
+            static ref REGEX_CAPTION: Regex = Regex::new(r"^(.*) \((COM\d+)\)$").unwrap();
+            static ref REGEX_DEVICE_ID: Regex =
+                Regex::new(r"(?i)^USB\\VID_([0-9A-F]{4})&PID_([0-9A-F]{4})\\(.*)$").unwrap();
+        }
+        let query_string = "SELECT Caption, DeviceID, Manufacturer FROM Win32_PnPEntity WHERE ClassGuid=\"{4d36e978-e325-11ce-bfc1-08002be10318}\"";
+
+        let conn = WMIConnection::new(COMLibrary::new()?)?;
+        let results: Vec<HashMap<String, Variant>> = conn.raw_query(query_string)?;
+        let mut ports = Vec::new();
+        for result in results {
+            let cap = match get_string(&result, "Caption").and_then(|c| REGEX_CAPTION.captures(c)) {
+                Some(cap) => cap,
+                None => continue,
+            };
+            let mut port = PortInfo {
+                name: cap.get(2).unwrap().as_str().into(),
+                product: Some(cap.get(1).unwrap().as_str().into()),
+                manufacturer: get_string(&result, "Manufacturer").map(Into::into),
+                ..Default::default()
+            };
+            if let Some(id) =
+                get_string(&result, "DeviceID").and_then(|d| REGEX_DEVICE_ID.captures(d))
+            {
+                port.vid = u16::from_str_radix(id.get(1).unwrap().as_str(), 16).ok();
+                port.pid = u16::from_str_radix(id.get(2).unwrap().as_str(), 16).ok();
+                // Instance IDs containing '&' are generated by Windows, not a device serial.
+                let instance = id.get(3).unwrap().as_str();
+                if !instance.contains('&') {
+                    port.serial_number = Some(instance.into());
+                }
+            }
+            ports.push(port);
+        }
+        ports.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(ports)
+    }
+}
@@ -0,0 +1,100 @@
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::protocol::{ACK, NAK};
+use crate::transport::ClockTransport;
+
+pub enum Answer {
+    Ack,
+    Nak,
+    Silent,
+}
+
+pub enum SendError {
+    Io(io::Error),
+    // The frame was not acknowledged before the deadline.
+    Unacknowledged { attempts: u32 },
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SendError::Io(e) => write!(f, "{}", e),
+            SendError::Unacknowledged { attempts } => write!(
+                f,
+                "frame not acknowledged after {} attempts before the commit deadline",
+                attempts
+            ),
+        }
+    }
+}
+
+// Skips anything that is neither ACK nor NAK, such as a late answer to an
+// earlier query.
+pub fn await_answer(transport: &mut dyn ClockTransport, timeout: Duration) -> io::Result<Answer> {
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 1];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(Answer::Silent);
+        }
+        if transport.receive(&mut buf, remaining)? == 0 {
+            continue;
+        }
+        match buf[0] {
+            ACK => return Ok(Answer::Ack),
+            NAK => return Ok(Answer::Nak),
+            other => debug!("Ignoring byte {:#04x} while waiting for ACK", other),
+        }
+    }
+}
+
+// Discards answers still arriving for frames of an earlier attempt, so they
+// are not taken for answers to the next one.
+pub fn drain(transport: &mut dyn ClockTransport) -> io::Result<()> {
+    let mut buf = [0u8; 64];
+    loop {
+        let len = transport.receive(&mut buf, Duration::ZERO)?;
+        if len == 0 {
+            return Ok(());
+        }
+        debug!("Discarding stale bytes {:02x?}", &buf[..len]);
+    }
+}
+
+// Sends `frame` and, with an ACK timeout, repeats it until the device
+// acknowledges it or `deadline` passes.
+pub fn send_frame(
+    transport: &mut dyn ClockTransport,
+    frame: &[u8],
+    ack_timeout: Option<Duration>,
+    deadline: Instant,
+) -> Result<(), SendError> {
+    let mut attempts = 0;
+    loop {
+        transport.send(frame).map_err(SendError::Io)?;
+        attempts += 1;
+        let ack_timeout = match ack_timeout {
+            Some(timeout) => timeout,
+            None => return Ok(()),
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let answer = await_answer(transport, ack_timeout.min(remaining)).map_err(SendError::Io)?;
+        let reason = match answer {
+            Answer::Ack => return Ok(()),
+            Answer::Nak => "NAK",
+            Answer::Silent => "no answer",
+        };
+        if deadline <= Instant::now() {
+            return Err(SendError::Unacknowledged { attempts });
+        }
+        debug!(
+            "Frame {:02x?} got {} on attempt {}, sending it again",
+            frame, reason, attempts
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info, warn};
+
+use crate::config::Config;
+use crate::sync::{self, Clock, SyncOptions};
+
+// Multicast group udevd rebroadcasts processed events on, after device nodes
+// and symlinks exist.
+const UDEV_MONITOR_GROUP: u32 = 2;
+const UDEV_MONITOR_MAGIC: u32 = 0xfeedcafe;
+const UDEV_HEADER_PREFIX: &[u8] = b"libudev\0";
+
+pub struct Uevent {
+    pub properties: HashMap<String, String>,
+}
+
+impl Uevent {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(String::as_str)
+    }
+}
+
+pub struct UeventSocket {
+    fd: OwnedFd,
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buf.get(offset..offset + 4)?;
+    Some(u32::from_ne_bytes(bytes.try_into().unwrap()))
+}
+
+fn parse_udev_message(buf: &[u8]) -> Option<Uevent> {
+    if !buf.starts_with(UDEV_HEADER_PREFIX) {
+        return None;
+    }
+    if u32::from_be(read_u32(buf, 8)?) != UDEV_MONITOR_MAGIC {
+        return None;
+    }
+    let properties_off = read_u32(buf, 16)? as usize;
+    let properties_len = read_u32(buf, 20)? as usize;
+    let blob = buf.get(properties_off..properties_off.checked_add(properties_len)?)?;
+    let properties = blob
+        .split(|&b| b == 0)
+        .filter_map(|entry| {
+            let entry = std::str::from_utf8(entry).ok()?;
+            let (key, value) = entry.split_once('=')?;
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect();
+    Some(Uevent { properties })
+}
+
+impl UeventSocket {
+    pub fn open() -> io::Result<UeventSocket> {
+        unsafe {
+            let fd = libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_KOBJECT_UEVENT,
+            );
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let fd = OwnedFd::from_raw_fd(fd);
+
+            let on: libc::c_int = 1;
+            if libc::setsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PASSCRED,
+                &on as *const _ as *const libc::c_void,
+                mem::size_of_val(&on) as libc::socklen_t,
+            ) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut addr: libc::sockaddr_nl = mem::zeroed();
+            addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            addr.nl_groups = UDEV_MONITOR_GROUP;
+            if libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const _ as *const libc::sockaddr,
+                mem::size_of_val(&addr) as libc::socklen_t,
+            ) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(UeventSocket { fd })
+        }
+    }
+
+    // Waits up to `timeout` for the next event. Messages not sent by root are
+    // dropped, like libudev does.
+    pub fn recv(&self, timeout: Option<Duration>) -> io::Result<Option<Uevent>> {
+        let timeout_ms = match timeout {
+            Some(timeout) => timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int,
+            None => -1,
+        };
+        let mut pollfd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(None);
+            }
+            return Err(err);
+        }
+        if ready == 0 {
+            return Ok(None);
+        }
+
+        let mut buf = [0u8; 8192];
+        let mut cmsg_buf = [0u8; 64];
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        let uid = unsafe {
+            let mut msg: libc::msghdr = mem::zeroed();
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = cmsg_buf.len() as _;
+            let len = libc::recvmsg(self.fd.as_raw_fd(), &mut msg, 0);
+            if len < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if msg.msg_flags & libc::MSG_TRUNC != 0 {
+                return Ok(None);
+            }
+            iov.iov_len = len as usize;
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            if cmsg.is_null()
+                || (*cmsg).cmsg_level != libc::SOL_SOCKET
+                || (*cmsg).cmsg_type != libc::SCM_CREDENTIALS
+            {
+                return Ok(None);
+            }
+            let cred = (libc::CMSG_DATA(cmsg) as *const libc::ucred).read_unaligned();
+            cred.uid
+        };
+        if uid != 0 {
+            debug!("Ignoring uevent from uid {}", uid);
+            return Ok(None);
+        }
+        Ok(parse_udev_message(&buf[..iov.iov_len]))
+    }
+}
+
+fn sync_new_device(
+    config: &Config,
+    options: &SyncOptions,
+    devname: &str,
+    synced: &mut HashMap<String, (String, Instant)>,
+    min_interval: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let matched = crate::discover(config)?
+        .into_iter()
+        .find(|matched| matched.port.name == devname);
+    let matched = match matched {
+        Some(matched) => matched,
+        None => {
+            debug!("{} does not match any rule, ignoring", devname);
+            return Ok(());
+        }
+    };
+
+    let target = config.target(&matched.port)?;
+    let id = matched.port.identity();
+    // Devices that can report their time are checked instead of trusted to
+    // still be right for `min_interval`.
+    if !target.profile.readback {
+        if let Some((_, last)) = synced.get(&id) {
+            if last.elapsed() < min_interval {
+                info!(
+                    "{} ({}) was synced {:?} ago, skipping",
+                    devname,
+                    id,
+                    last.elapsed()
+                );
+                return Ok(());
+            }
+        }
+    }
+
+    let (mut clocks, _locks) = sync::open_clocks(&[target], options);
+    if let Some(Clock {
+        port,
+        profile,
+        transport: Ok(transport),
+    }) = clocks.first_mut()
+    {
+        if profile.readback {
+            match sync::read_offset(port, transport.as_mut(), profile.checksum, "on arrival") {
+                Ok(offset) if sync::within_tolerance(offset, options.tolerance) => {
+                    info!("{} ({}) is within tolerance, skipping", devname, id);
+                    return Ok(());
+                }
+                Ok(_) => {}
+                Err(e) => warn!("{}: failed to read device time: {}", devname, e),
+            }
+        }
+    }
+
+    info!("{} appeared (rule {}), syncing", devname, matched.rule);
+    for report in sync::sync_clocks(clocks, options)?.reports {
+        report.result?;
+    }
+    synced.insert(id, (devname.to_string(), Instant::now()));
+    Ok(())
+}
+
+pub fn watch(
+    config: &Config,
+    options: &SyncOptions,
+    debounce: Duration,
+    min_interval: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let socket = UeventSocket::open()?;
+    info!("Watching for serial devices");
+
+    // Re-enumeration produces bursts of add/remove events; a device is only
+    // synced once it has been quiet for `debounce`.
+    let mut pending: HashMap<String, Instant> = HashMap::new();
+    // Devices synced recently, by identity, with the node they were synced
+    // on. Unplugging one loses its time, so that forgets it.
+    let mut synced: HashMap<String, (String, Instant)> = HashMap::new();
+    loop {
+        let timeout = pending
+            .values()
+            .map(|last| (*last + debounce).saturating_duration_since(Instant::now()))
+            .min();
+        if let Some(event) = socket.recv(timeout)? {
+            if let (Some("tty"), Some(action), Some(devname)) = (
+                event.get("SUBSYSTEM"),
+                event.get("ACTION"),
+                event.get("DEVNAME"),
+            ) {
+                debug!("uevent {} {}", action, devname);
+                match action {
+                    "add" | "change" => {
+                        pending.insert(devname.to_string(), Instant::now());
+                    }
+                    "remove" => {
+                        pending.remove(devname);
+                        synced.retain(|_, (synced_devname, _)| synced_devname != devname);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let due: Vec<String> = pending
+            .iter()
+            .filter(|(_, last)| last.elapsed() >= debounce)
+            .map(|(devname, _)| devname.clone())
+            .collect();
+        for devname in due {
+            pending.remove(&devname);
+            if let Err(e) = sync_new_device(config, options, &devname, &mut synced, min_interval) {
+                error!("{}: {}", devname, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::device::Target;
+use crate::profile::DeviceProfile;
+use crate::protocol::{DecodeError, Frame, CAP_ACK, CAP_CHECKSUM, CAP_READBACK, CAP_SET_DATE};
+use crate::transport::ClockTransport;
+
+pub struct Identity {
+    pub model: u16,
+    pub major: u8,
+    pub minor: u8,
+    pub capabilities: u8,
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = [
+            (CAP_SET_DATE, "date"),
+            (CAP_READBACK, "readback"),
+            (CAP_ACK, "ack"),
+            (CAP_CHECKSUM, "checksum"),
+        ]
+        .iter()
+        .filter(|(bit, _)| self.capabilities & bit != 0)
+        .map(|(_, name)| *name)
+        .collect();
+        write!(
+            f,
+            "model {} firmware {}.{} supporting [{}]",
+            self.model,
+            self.major,
+            self.minor,
+            names.join(", ")
+        )
+    }
+}
+
+impl Identity {
+    fn supports(&self, capability: u8) -> bool {
+        self.capabilities & capability != 0
+    }
+
+    // The profile configured for this model, or else the target's own, with
+    // every feature the firmware lacks switched off.
+    pub fn profile(&self, target: &Target) -> DeviceProfile {
+        let profile = target
+            .model_profiles
+            .get(&self.model)
+            .unwrap_or(&target.profile);
+        DeviceProfile {
+            set_date: profile.set_date && self.supports(CAP_SET_DATE),
+            readback: profile.readback && self.supports(CAP_READBACK),
+            ack: profile.ack && self.supports(CAP_ACK),
+            checksum: profile.checksum && self.supports(CAP_CHECKSUM),
+            ..profile.clone()
+        }
+    }
+}
+
+pub fn identify(
+    transport: &mut dyn ClockTransport,
+    timeout: Duration,
+) -> Result<Identity, Box<dyn Error>> {
+    transport.send(&Frame::Identify.encode())?;
+    let deadline = Instant::now() + timeout;
+    let mut reply = Vec::new();
+    let mut buf = [0u8; 64];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err("Device did not answer the identify request".into());
+        }
+        let len = transport.receive(&mut buf, remaining)?;
+        reply.extend_from_slice(&buf[..len]);
+        match Frame::decode(&reply) {
+            Ok((
+                Frame::Identity {
+                    model,
+                    major,
+                    minor,
+                    capabilities,
+                },
+                _,
+            )) => {
+                return Ok(Identity {
+                    model,
+                    major,
+                    minor,
+                    capabilities,
+                })
+            }
+            Ok((frame, _)) => {
+                return Err(format!("Unexpected reply to identify request: {}", frame).into())
+            }
+            Err(DecodeError::Truncated { .. }) => {}
+            Err(e) => {
+                return Err(
+                    format!("Invalid reply to identify request {:02x?}: {}", reply, e).into(),
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::env;
+use std::error::Error;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Advisory lock on a per-device file, held until dropped. Keyed by device
+// identity rather than port name so that re-enumeration under a different
+// /dev node still collides.
+pub struct DeviceLock {
+    _file: File,
+}
+
+fn lock_dir() -> PathBuf {
+    let run_lock = Path::new("/run/lock");
+    if cfg!(unix) && run_lock.is_dir() {
+        run_lock.into()
+    } else {
+        env::temp_dir()
+    }
+}
+
+fn lock_path(identity: &str) -> PathBuf {
+    let name: String = identity
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    lock_dir().join(format!("mytimesync-{}.lock", name))
+}
+
+pub fn acquire(identity: &str, timeout: Option<Duration>) -> Result<DeviceLock, Box<dyn Error>> {
+    let path = lock_path(identity);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .map_err(|e| format!("Failed to open lock file {}: {}", path.display(), e))?;
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut waiting = false;
+    loop {
+        match file.try_lock() {
+            Ok(()) => {
+                debug!("Locked {}", path.display());
+                return Ok(DeviceLock { _file: file });
+            }
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        match deadline {
+            Some(deadline) if Instant::now() < deadline => {
+                if !waiting {
+                    info!("Device {} is busy, waiting", identity);
+                    waiting = true;
+                }
+                thread::sleep(LOCK_POLL_INTERVAL);
+            }
+            _ => {
+                return Err(format!(
+                    "Device {} is busy (locked by another instance via {})",
+                    identity,
+                    path.display()
+                )
+                .into())
+            }
+        }
+    }
+}
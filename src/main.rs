@@ -1,107 +1,488 @@
-use chrono::{DateTime, Datelike, Duration, Local, TimeZone, Timelike};
-use log::{error, info, warn, LevelFilter};
-use regex::Regex;
-use std::collections::HashMap;
+mod calibration;
+mod config;
+mod device;
+mod discovery;
+mod handshake;
+#[cfg(target_os = "linux")]
+mod hotplug;
+mod identify;
+mod lock;
+mod probe;
+mod profile;
+mod protocol;
+mod readback;
+mod rules;
+mod schedule;
+mod selection;
+mod sync;
+mod transport;
+mod wait;
+
+use clap::{Parser, Subcommand};
+use log::{debug, error, info, LevelFilter};
 use std::error::Error;
-use std::thread;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use calibration::CalibrationDb;
+use config::Config;
+use device::Target;
+use discovery::PortInfo;
+use rules::MatchedPort;
+use selection::Selector;
+use sync::SyncOptions;
+use transport::{Direction, Trace};
+use wait::WaitStrategy;
+
+#[derive(Parser)]
+#[command(version, about = "Synchronize serial clocks to the system time")]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// TOML file with device matching rules
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Serial port name, e.g. /dev/ttyUSB0, COM3, tcp://host:port, rfc2217://host:port or pty:///dev/pts/N
+    #[arg(long, group = "selector")]
+    port: Option<String>,
+
+    /// USB serial number of the adapter
+    #[arg(long, group = "selector")]
+    serial_number: Option<String>,
+
+    /// Physical port path, e.g. pci-0000:00:14.0-usb-0:2:1.0-port0
+    #[arg(long, group = "selector")]
+    location: Option<String>,
+
+    /// Device alias defined in the config file
+    #[arg(long, group = "selector")]
+    alias: Option<String>,
+
+    /// Sync every matched device on the same second boundary
+    #[arg(long, conflicts_with = "selector")]
+    all: bool,
+
+    /// Wait up to this many seconds for a device locked by another instance
+    #[arg(long)]
+    lock_timeout: Option<u64>,
+
+    /// Largest offset in milliseconds a device may read back after the sync
+    #[arg(long, default_value_t = 1000)]
+    tolerance_ms: i64,
+
+    /// Second boundaries to try before giving up when a commit deadline is missed
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+    attempts: u32,
+
+    /// How to wait for the commit time
+    #[arg(long, value_enum, default_value_t = WaitStrategy::Sleep)]
+    wait: WaitStrategy,
+
+    /// Record every byte sent and received to this pcap file
+    #[arg(long)]
+    trace: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Keep running and sync matching devices as they are plugged in
+    Watch {
+        /// Milliseconds a device must stay quiet before it is synced
+        #[arg(long, default_value_t = 1000)]
+        debounce_ms: u64,
+
+        /// Seconds after a sync during which the same device is not synced again
+        #[arg(long, default_value_t = 60)]
+        min_interval: u64,
+    },
+
+    /// Decode captured bytes, given as hex, into protocol frames
+    Decode {
+        /// Expect the checksummed frame variant
+        #[arg(long)]
+        checksum: bool,
+
+        #[arg(required = true)]
+        hex: Vec<String>,
+    },
 
-use lazy_static::lazy_static;
+    /// Send a raw command to the selected device and print what it answers
+    Send {
+        /// Command letters, e.g. Sb
+        command: String,
 
-use wmi::{COMLibrary, Variant, WMIConnection};
+        /// Integer arguments as VALUE or VALUE:GROUPS of 7 bits
+        args: Vec<protocol::Argument>,
 
-fn get_serial() -> Result<String, Box<dyn Error>> {
-    lazy_static! {
-        static ref REGEX_SERIAL_PORT: Regex = Regex::new(r"USB-SERIAL CH340 \((COM\d+)\)").unwrap();
+        /// Milliseconds to wait for the device to answer
+        #[arg(long, default_value_t = 500)]
+        wait_ms: u64,
+    },
+
+    /// Measure the commit latency of the selected devices and store it for later syncs
+    Calibrate {
+        #[arg(long, value_enum, default_value_t = calibration::Method::Echo)]
+        method: calibration::Method,
+
+        #[arg(long, default_value_t = 50, value_parser = clap::value_parser!(u32).range(1..))]
+        trials: u32,
+
+        /// Byte sent for echo trials, e.g. 0x55
+        #[arg(long, default_value = "0x55", value_parser = parse_byte)]
+        byte: u8,
+    },
+
+    /// Decode a trace recorded with --trace and time it against the sync boundary
+    Replay {
+        /// Expect the checksummed frame variant
+        #[arg(long)]
+        checksum: bool,
+
+        trace: PathBuf,
+    },
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => text.parse(),
     }
-    let query_string =  "SELECT Caption FROM Win32_PnPEntity WHERE ClassGuid=\"{4d36e978-e325-11ce-bfc1-08002be10318}\"";
+    .map_err(|e| e.to_string())
+}
 
-    let conn = WMIConnection::new(COMLibrary::new()?)?;
-    let results: Vec<HashMap<String, Variant>> = conn.raw_query(query_string)?;
-    let mut result_ports: Vec<String> = Vec::new();
-    for result in results {
-        if let Some(Variant::String(caption)) = result.get("Caption") {
-            if let Some(cap) = REGEX_SERIAL_PORT.captures(caption) {
-                let port_number = cap.get(1).unwrap().as_str();
-                result_ports.push(port_number.into());
-            }
+impl Args {
+    fn selector(&self) -> Option<Selector> {
+        if let Some(port) = &self.port {
+            Some(Selector::Port(port.clone()))
+        } else if let Some(serial) = &self.serial_number {
+            Some(Selector::SerialNumber(serial.clone()))
+        } else if let Some(location) = &self.location {
+            Some(Selector::Location(location.clone()))
+        } else {
+            self.alias.clone().map(Selector::Alias)
         }
     }
+}
 
-    if result_ports.is_empty() {
-        return Err("No serial ports found".into());
+fn discover(config: &Config) -> Result<Vec<MatchedPort>, Box<dyn Error>> {
+    let ports = discovery::platform_discovery().list_ports()?;
+    for port in &ports {
+        debug!(
+            "Found serial port {}: vid={:04x?} pid={:04x?} manufacturer={:?} product={:?} serial={:?} location={:?}",
+            port.name, port.vid, port.pid, port.manufacturer, port.product, port.serial_number, port.location
+        );
     }
+    Ok(rules::match_ports(ports, &config.rules()))
+}
+
+fn get_serial(
+    config: &Config,
+    selector: Option<&Selector>,
+    all: bool,
+) -> Result<Vec<Target>, Box<dyn Error>> {
+    if let Some(selector) = selector {
+        let identity = selector.resolve(&config.devices)?;
+        if let Some(name) = identity.port.filter(|name| transport::is_url(name)) {
+            let port = PortInfo {
+                name,
+                ..Default::default()
+            };
+            return Ok(vec![config.target(&port)?]);
+        }
+    }
+    let result_ports = discover(config)?;
+    if all {
+        if result_ports.is_empty() {
+            return Err("No serial ports found".into());
+        }
+        return result_ports
+            .iter()
+            .map(|m| config.target(&m.port))
+            .collect();
+    }
+    let selected = selection::select_port(result_ports, selector, &config.devices)?;
+    info!("Using port {} (rule {})", selected.port.name, selected.rule);
+    Ok(vec![config.target(&selected.port)?])
+}
 
-    if result_ports.len() > 1 {
-        warn!(
-            "Multiple serial ports found, using first one: {}",
-            result_ports[0]
+#[cfg(target_os = "linux")]
+fn watch(
+    config: &Config,
+    options: &SyncOptions,
+    debounce: Duration,
+    min_interval: Duration,
+) -> Result<(), Box<dyn Error>> {
+    hotplug::watch(config, options, debounce, min_interval)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch(_: &Config, _: &SyncOptions, _: Duration, _: Duration) -> Result<(), Box<dyn Error>> {
+    Err("Hotplug monitoring is only supported on Linux".into())
+}
+
+fn decode(hex: &str, checked: bool) -> Result<(), Box<dyn Error>> {
+    let bytes = protocol::parse_hex(hex)?;
+    let decoded = protocol::decode_stream(&bytes, checked);
+    for (offset, frame) in decoded.frames {
+        let len = if checked {
+            frame.encode_checked().len()
+        } else {
+            frame.encode().len()
+        };
+        println!(
+            "{:04}: {:02x?} {}",
+            offset,
+            &bytes[offset..offset + len],
+            frame
         );
     }
+    if let Some((offset, e)) = decoded.error {
+        return Err(format!("at offset {}: {}", offset, e).into());
+    }
+    Ok(())
+}
 
-    Ok(result_ports.into_iter().next().unwrap())
+fn send(
+    targets: &[Target],
+    options: &SyncOptions,
+    command: &str,
+    arguments: &[protocol::Argument],
+    wait: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let (clocks, _locks) = sync::open_clocks(targets, options);
+    for clock in clocks {
+        let frame = protocol::command_frame(command, arguments, clock.profile.checksum)?;
+        let mut transport = clock.transport?;
+        transport.send(&frame)?;
+        println!("{} > {:02x?}", clock.port, frame);
+        let deadline = Instant::now() + wait;
+        let mut buf = [0u8; 256];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            let len = transport.receive(&mut buf, remaining)?;
+            if len > 0 {
+                println!(
+                    "{} < {:02x?} {}",
+                    clock.port,
+                    &buf[..len],
+                    describe(&buf[..len], clock.profile.checksum)
+                );
+            }
+        }
+    }
+    Ok(())
 }
 
-fn time_trunc_second(time: &DateTime<Local>) -> DateTime<Local> {
-    Local
-        .with_ymd_and_hms(
-            time.year(),
-            time.month(),
-            time.day(),
-            time.hour(),
-            time.minute(),
-            time.second(),
-        )
-        .unwrap()
+fn calibrate(
+    config: &Config,
+    targets: &[Target],
+    options: &SyncOptions,
+    method: calibration::Method,
+    trials: usize,
+    byte: u8,
+) -> Result<(), Box<dyn Error>> {
+    let path = config.calibration_path();
+    let mut db = CalibrationDb::load(&path)?;
+    // Opened without stored calibrations, so the transports report the
+    // estimate from their line settings.
+    let targets: Vec<Target> = targets
+        .iter()
+        .map(|target| Target {
+            calibration: None,
+            ..target.clone()
+        })
+        .collect();
+    let (clocks, _locks) = sync::open_clocks(&targets, options);
+    for (target, clock) in targets.iter().zip(clocks) {
+        let mut transport = clock.transport?;
+        let calibration = calibration::measure(transport.as_mut(), method, byte, trials)
+            .map_err(|e| format!("{}: {}", target.name, e))?;
+        info!(
+            "{}: latency {} µs, jitter {} µs over {} trials (line settings estimate {} µs)",
+            target.name,
+            calibration.median_us,
+            calibration.jitter_us,
+            calibration.trials,
+            transport.latency().as_micros()
+        );
+        db.insert(&target.identity, calibration);
+    }
+    db.save(&path)?;
+    info!("Saved calibrations to {}", path.display());
+    Ok(())
 }
 
-fn construct_data_buf(time: impl Timelike) -> [u8; 6] {
-    let seconds = ((time.hour() * 60) + time.minute()) * 60 + time.second();
-    let mut result = *b"Sb\x00\x00\x00\x00";
-    result[5] = ((seconds & 0x7f) | 0x80) as u8;
-    result[4] = (((seconds >> 7) & 0x7f) | 0x80) as u8;
-    result[3] = (((seconds >> 14) & 0x7f) | 0x80) as u8;
-    result[2] = (((seconds >> 21) & 0x7f) | 0x80) as u8;
-    result
+// Frames in `data`, with ACK and NAK answers spelled out.
+fn describe(data: &[u8], checked: bool) -> String {
+    let mut parts = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let decoded = protocol::decode_stream(rest, checked);
+        parts.extend(decoded.frames.iter().map(|(_, frame)| frame.to_string()));
+        match decoded.error {
+            Some((offset, _)) if rest[offset] == protocol::ACK => parts.push("ACK".into()),
+            Some((offset, _)) if rest[offset] == protocol::NAK => parts.push("NAK".into()),
+            Some((_, e)) => {
+                parts.push(format!("<{}>", e));
+                break;
+            }
+            None => break,
+        }
+        rest = &rest[decoded.error.map_or(rest.len(), |(offset, _)| offset + 1)..];
+    }
+    parts.join(", ")
+}
+
+fn replay(path: &Path, checked: bool) -> Result<(), Box<dyn Error>> {
+    let mut boundary = None;
+    for event in transport::read_trace(path)? {
+        let monotonic = event.monotonic.as_secs_f64();
+        let direction = match event.direction {
+            Direction::Boundary => {
+                println!(
+                    "{:12.6} boundary {}",
+                    monotonic,
+                    event.wall.format("%H:%M:%S%.3f")
+                );
+                boundary = Some(event.wall);
+                continue;
+            }
+            Direction::Sent => ">",
+            Direction::Received => "<",
+        };
+        let timing = match boundary {
+            Some(boundary) => format!(
+                "{:+} µs",
+                (event.wall - boundary)
+                    .num_microseconds()
+                    .unwrap_or(i64::MAX)
+            ),
+            None => "before any boundary".into(),
+        };
+        println!(
+            "{:12.6} {} {} {:02x?} {} ({})",
+            monotonic,
+            event.port,
+            direction,
+            event.data,
+            describe(&event.data, checked),
+            timing
+        );
+    }
+    Ok(())
 }
 
 fn main() {
     env_logger::builder()
         .filter_level(LevelFilter::Trace)
         .init();
-    if let Err(e) = inner_main() {
+    let args = Args::parse();
+    if let Err(e) = inner_main(&args) {
         error!("main error: {}", e);
     }
 }
 
-fn inner_main() -> Result<(), Box<dyn Error>> {
-    let serial_port_num = get_serial()?;
-    info!("Serial port number: {}", serial_port_num);
-    let mut serial = serialport::new(serial_port_num, 115200).open()?;
-    let now = Local::now();
-    let mut next = now;
-    let (next_sync_time, dist) = loop {
-        next = next + Duration::seconds(1);
-        let next_sync_time = time_trunc_second(&next);
-        let dist = next_sync_time - now;
-        if dist > Duration::microseconds(100) {
-            break (next_sync_time, dist);
-        }
+fn inner_main(args: &Args) -> Result<(), Box<dyn Error>> {
+    let config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
     };
-
-    let buf = construct_data_buf(next_sync_time);
-    serial.write(&buf)?;
-
-    let sleep_duration = next_sync_time - Local::now();
-    if sleep_duration < Duration::zero() {
-        error!("Failed to finish operation within {:?}", dist);
-        return Err("Failed to finish operation.".into());
+    let options = SyncOptions {
+        lock_timeout: args.lock_timeout.map(Duration::from_secs),
+        tolerance: chrono::Duration::milliseconds(args.tolerance_ms),
+        attempts: args.attempts,
+        trace: args.trace.as_deref().map(Trace::create).transpose()?,
+        wait: args.wait,
+    };
+    match &args.command {
+        Some(Command::Watch {
+            debounce_ms,
+            min_interval,
+        }) => {
+            return watch(
+                &config,
+                &options,
+                Duration::from_millis(*debounce_ms),
+                Duration::from_secs(*min_interval),
+            )
+        }
+        Some(Command::Decode { checksum, hex }) => return decode(&hex.join(" "), *checksum),
+        Some(Command::Replay { checksum, trace }) => return replay(trace, *checksum),
+        Some(Command::Send { .. }) | Some(Command::Calibrate { .. }) | None => {}
     }
-    let sleep_duration = sleep_duration.to_std()?;
-    thread::sleep(sleep_duration);
 
-    serial.write(b"c")?;
+    let targets = get_serial(&config, args.selector().as_ref(), args.all)?;
+    let names: Vec<&str> = targets.iter().map(|t| t.name.as_str()).collect();
+    info!("Serial ports: {}", names.join(", "));
 
-    info!("Sync finished to time {}", next_sync_time);
+    if let Some(Command::Send {
+        command,
+        args: arguments,
+        wait_ms,
+    }) = &args.command
+    {
+        return send(
+            &targets,
+            &options,
+            command,
+            arguments,
+            Duration::from_millis(*wait_ms),
+        );
+    }
+    if let Some(Command::Calibrate {
+        method,
+        trials,
+        byte,
+    }) = &args.command
+    {
+        return calibrate(
+            &config,
+            &targets,
+            &options,
+            *method,
+            *trials as usize,
+            *byte,
+        );
+    }
+    let run = sync::sync_targets(&targets, &options)?;
+    let reports = run.reports;
+    let mut failed = 0;
+    for report in &reports {
+        let commit = match report.commit_offset {
+            Some(offset) => format!(
+                ", commit {:+} µs",
+                offset.num_microseconds().unwrap_or(i64::MAX)
+            ),
+            None => String::new(),
+        };
+        match &report.result {
+            Ok(None) => info!(
+                "{}: synced to {}{}",
+                report.port,
+                run.sync_time.format("%H:%M:%S"),
+                commit
+            ),
+            Ok(Some(offset)) => info!(
+                "{}: synced to {}{}, offset {} ms",
+                report.port,
+                run.sync_time.format("%H:%M:%S"),
+                commit,
+                offset.num_milliseconds()
+            ),
+            Err(e) => {
+                error!("{}: {}", report.port, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} clocks failed to sync", failed, reports.len()).into());
+    }
 
     Ok(())
 }
@@ -0,0 +1,136 @@
+use std::error::Error;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+
+use crate::device::{ProbeConfig, ReadyConfig, Target};
+use crate::transport::{self, ClockTransport, PortSettings, Trace};
+
+// With a trace, everything from probing and boot on is recorded.
+pub fn open_device(
+    target: &Target,
+    trace: Option<&Trace>,
+) -> Result<Box<dyn ClockTransport>, Box<dyn Error>> {
+    let settings = target.device.port_settings(&target.profile)?;
+    match &target.device.probe {
+        Some(probe) => probe_baud_rate(target, settings, probe, trace),
+        None => {
+            let mut transport = open(&target.name, &settings, trace)?;
+            wait_ready(target, transport.as_mut())?;
+            Ok(transport)
+        }
+    }
+}
+
+fn open(
+    name: &str,
+    settings: &PortSettings,
+    trace: Option<&Trace>,
+) -> Result<Box<dyn ClockTransport>, Box<dyn Error>> {
+    let transport = transport::open(name, settings)?;
+    Ok(match trace {
+        Some(trace) => trace.wrap(name, transport),
+        None => transport,
+    })
+}
+
+// Boards that reset when the port opens drop what is sent while they boot.
+fn wait_ready(target: &Target, transport: &mut dyn ClockTransport) -> Result<(), Box<dyn Error>> {
+    match &target.device.ready {
+        Some(ReadyConfig::Delay { delay_ms }) => {
+            debug!("Waiting {} ms for {} to boot", delay_ms, target.name);
+            thread::sleep(Duration::from_millis(*delay_ms));
+        }
+        Some(ReadyConfig::Banner { banner, timeout_ms }) => {
+            wait_for_banner(
+                transport,
+                banner.as_bytes(),
+                Duration::from_millis(*timeout_ms),
+            )
+            .map_err(|e| format!("{}: {}", target.name, e))?;
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+// Bytes before the banner are boot noise and are dropped with it.
+fn wait_for_banner(
+    transport: &mut dyn ClockTransport,
+    banner: &[u8],
+    timeout: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let deadline = Instant::now() + timeout;
+    let mut seen = Vec::new();
+    let mut buf = [0u8; 64];
+    while !seen.windows(banner.len().max(1)).any(|w| w == banner) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(format!(
+                "device did not print its ready banner within {:?}, got {:02x?}",
+                timeout, seen
+            )
+            .into());
+        }
+        let len = transport.receive(&mut buf, remaining)?;
+        seen.extend_from_slice(&buf[..len]);
+    }
+    debug!("Ready banner seen after {:02x?}", seen);
+    Ok(())
+}
+
+fn answered(
+    transport: &mut dyn ClockTransport,
+    probe: &ProbeConfig,
+) -> Result<bool, Box<dyn Error>> {
+    let deadline = Instant::now() + probe.timeout();
+    let expected_len = probe.response_prefix.len().max(1);
+    let mut reply = Vec::new();
+    let mut buf = [0u8; 64];
+    while reply.len() < expected_len {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(false);
+        }
+        let len = transport.receive(&mut buf, remaining)?;
+        reply.extend_from_slice(&buf[..len]);
+    }
+    debug!("Probe reply {:02x?}", reply);
+    Ok(reply.starts_with(&probe.response_prefix))
+}
+
+// Tries each candidate rate in turn and keeps the first one the device
+// answers the probe request at.
+fn probe_baud_rate(
+    target: &Target,
+    settings: PortSettings,
+    probe: &ProbeConfig,
+    trace: Option<&Trace>,
+) -> Result<Box<dyn ClockTransport>, Box<dyn Error>> {
+    let name = &target.name;
+    let baud_rates = if probe.baud_rates.is_empty() {
+        vec![settings.baud_rate]
+    } else {
+        probe.baud_rates.clone()
+    };
+    for &baud_rate in &baud_rates {
+        let settings = PortSettings {
+            baud_rate,
+            ..settings
+        };
+        let mut transport = open(name, &settings, trace)?;
+        // A banner is only readable at the right rate.
+        if let Err(e) = wait_ready(target, transport.as_mut()) {
+            debug!("{} at {} baud: {}", name, baud_rate, e);
+            continue;
+        }
+        transport.send(&probe.request)?;
+        if answered(transport.as_mut(), probe)? {
+            info!("{} answered at {} baud", name, baud_rate);
+            return Ok(transport);
+        }
+        debug!("{} did not answer at {} baud", name, baud_rate);
+    }
+    Err(format!("{} did not answer at any of {:?} baud", name, baud_rates).into())
+}
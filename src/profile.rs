@@ -0,0 +1,261 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Local, Timelike};
+use serde::Deserialize;
+
+use crate::protocol::{self, Frame};
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Literal {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl Literal {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Literal::Text(text) => text.as_bytes(),
+            Literal::Bytes(bytes) => bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Hour,
+    Minute,
+    Second,
+    SecondsOfDay,
+    Year,
+    Month,
+    Day,
+    // ISO numbering, 1 = Monday.
+    Weekday,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    // 7-bit groups with the high bit set, as in the native protocol.
+    #[default]
+    SevenBit,
+    // One decimal digit per nibble, e.g. for DS3231 registers.
+    Bcd,
+    // Zero-padded decimal digits.
+    Ascii,
+    // Big-endian unsigned bytes.
+    Binary,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum Part {
+    Literal {
+        literal: Literal,
+    },
+    Field {
+        field: Field,
+        #[serde(default)]
+        encoding: Encoding,
+        // Groups, bytes or digits depending on the encoding.
+        width: Option<usize>,
+    },
+}
+
+impl Field {
+    fn value(self, time: &DateTime<Local>) -> u32 {
+        match self {
+            Field::Hour => time.hour(),
+            Field::Minute => time.minute(),
+            Field::Second => time.second(),
+            Field::SecondsOfDay => time.num_seconds_from_midnight(),
+            Field::Year => time.year().max(0) as u32,
+            Field::Month => time.month(),
+            Field::Day => time.day(),
+            Field::Weekday => time.weekday().number_from_monday(),
+        }
+    }
+}
+
+impl Encoding {
+    fn default_width(self) -> usize {
+        match self {
+            Encoding::SevenBit | Encoding::Bcd | Encoding::Binary => 1,
+            Encoding::Ascii => 2,
+        }
+    }
+
+    // Values too large for `width` keep their low-order part, so a two-digit
+    // year is just `year` with width 2.
+    fn encode(self, value: u32, width: usize) -> Vec<u8> {
+        match self {
+            Encoding::SevenBit => protocol::encode_seven_bit(value, width),
+            Encoding::Bcd => (0..width)
+                .rev()
+                .map(|i| {
+                    let byte = (u64::from(value) / 100u64.pow(i as u32) % 100) as u8;
+                    ((byte / 10) << 4) | (byte % 10)
+                })
+                .collect(),
+            Encoding::Ascii => {
+                let digits = format!("{:0width$}", value, width = width);
+                digits.as_bytes()[digits.len() - width..].to_vec()
+            }
+            Encoding::Binary => value.to_be_bytes()[4 - width..].to_vec(),
+        }
+    }
+}
+
+// What a clock model understands. Devices pick one by name; `default` applies
+// to devices that name none and may itself be overridden in the config.
+//
+// Without `preload` the native `Sb`/`Sd`/`c` protocol is used. With it, the
+// preload frame is built from the listed parts and followed by `commit`; an
+// empty commit means the device acts on the preload itself, so it is sent at
+// the boundary instead.
+//
+// With `ack` the firmware answers every frame with ACK or NAK, and an
+// unacknowledged preload is sent again until the commit is due. With
+// `checksum` each preload frame carries a trailing checksum byte.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceProfile {
+    // Model number reported by identification, for devices that identify.
+    pub model: Option<u16>,
+    pub set_date: bool,
+    // Whether the firmware answers QueryTime, so syncs can be verified.
+    pub readback: bool,
+    pub baud_rate: Option<u32>,
+    // Delay between the commit reaching the device and the display updating.
+    pub latency_us: u64,
+    pub preload: Option<Vec<Part>>,
+    pub commit: Option<Literal>,
+    pub ack: bool,
+    pub ack_timeout_ms: u64,
+    pub checksum: bool,
+}
+
+impl Default for DeviceProfile {
+    fn default() -> DeviceProfile {
+        DeviceProfile {
+            model: None,
+            set_date: true,
+            readback: false,
+            baud_rate: None,
+            latency_us: 0,
+            preload: None,
+            commit: None,
+            ack: false,
+            ack_timeout_ms: 100,
+            checksum: false,
+        }
+    }
+}
+
+impl DeviceProfile {
+    pub fn load(path: &Path) -> Result<DeviceProfile, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read profile {}: {}", path.display(), e))?;
+        let profile = toml::from_str(&text)
+            .map_err(|e| format!("Failed to parse profile {}: {}", path.display(), e))?;
+        Ok(profile)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for part in self.preload.iter().flatten() {
+            if let Part::Field {
+                field,
+                encoding,
+                width: Some(width),
+            } = part
+            {
+                let max_width = match encoding {
+                    Encoding::SevenBit => 5,
+                    Encoding::Bcd => 5,
+                    Encoding::Ascii => 10,
+                    Encoding::Binary => 4,
+                };
+                if *width == 0 || *width > max_width {
+                    return Err(format!(
+                        "width {} of field {:?} is outside 1..={} for {:?} encoding",
+                        width, field, max_width, encoding
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Each frame is acknowledged separately by firmware with `ack`.
+    pub fn preload_frames(&self, time: &DateTime<Local>) -> Vec<Vec<u8>> {
+        let parts = match &self.preload {
+            Some(parts) => parts,
+            None => {
+                let mut frames = vec![Frame::set_time_of_day(*time)];
+                if self.set_date {
+                    frames.push(Frame::set_date(*time));
+                }
+                return frames
+                    .iter()
+                    .map(|frame| {
+                        if self.checksum {
+                            frame.encode_checked()
+                        } else {
+                            frame.encode()
+                        }
+                    })
+                    .collect();
+            }
+        };
+        let mut buf = Vec::new();
+        for part in parts {
+            match part {
+                Part::Literal { literal } => buf.extend_from_slice(literal.bytes()),
+                Part::Field {
+                    field,
+                    encoding,
+                    width,
+                } => {
+                    let width = width.unwrap_or_else(|| encoding.default_width());
+                    buf.extend(encoding.encode(field.value(time), width));
+                }
+            }
+        }
+        if self.checksum {
+            buf.push(protocol::checksum(&buf));
+        }
+        vec![buf]
+    }
+
+    pub fn commit_frame(&self) -> Vec<u8> {
+        match (&self.commit, &self.preload) {
+            (Some(commit), _) => commit.bytes().to_vec(),
+            (None, Some(_)) => Vec::new(),
+            (None, None) => Frame::Commit.encode(),
+        }
+    }
+
+    // Bytes written at the boundary. Fields have fixed widths, so the length
+    // is the same for any time.
+    pub fn commit_len(&self) -> usize {
+        match self.commit_frame().len() {
+            0 => self.preload_frames(&Local::now()).concat().len(),
+            len => len,
+        }
+    }
+
+    pub fn latency(&self) -> Duration {
+        Duration::from_micros(self.latency_us)
+    }
+
+    pub fn ack_timeout(&self) -> Option<Duration> {
+        self.ack.then(|| Duration::from_millis(self.ack_timeout_ms))
+    }
+}
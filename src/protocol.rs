@@ -0,0 +1,492 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{Datelike, NaiveDate, Timelike};
+
+pub const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+// Single-byte answers from firmware that acknowledges frames.
+pub const ACK: u8 = 0x06;
+pub const NAK: u8 = 0x15;
+
+const MARKER: u8 = 0x80;
+const SEVEN_BIT_MASK: u32 = 0x7f;
+const SECONDS_GROUPS: usize = 4;
+const YEAR_GROUPS: usize = 2;
+const MODEL_GROUPS: usize = 2;
+// Model, firmware major and minor version, and capabilities.
+const IDENTITY_LEN: usize = MODEL_GROUPS + 3;
+
+// Capability bits in an Identity frame.
+pub const CAP_SET_DATE: u8 = 0x01;
+pub const CAP_READBACK: u8 = 0x02;
+pub const CAP_ACK: u8 = 0x04;
+pub const CAP_CHECKSUM: u8 = 0x08;
+// Year, month, day and weekday.
+const DATE_LEN: usize = YEAR_GROUPS + 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frame {
+    // `S` `b` + seconds since midnight in four 7-bit groups; takes effect on
+    // the next commit.
+    SetTimeOfDay {
+        seconds: u32,
+    },
+    // `S` `d` + year in two 7-bit groups, then month, day and ISO weekday
+    // (1 = Monday) in one group each; latched by the same commit.
+    SetDate {
+        year: u16,
+        month: u8,
+        day: u8,
+        weekday: u8,
+    },
+    // `c`: latch the preloaded time.
+    Commit,
+    // `q`: ask the device for its current time.
+    QueryTime,
+    // `R` `b` + seconds since midnight, encoded like SetTimeOfDay; the
+    // device's answer to QueryTime.
+    TimeReport {
+        seconds: u32,
+    },
+    // `i`: ask the device what it is.
+    Identify,
+    // `R` `i` + model number in two 7-bit groups, then firmware major and
+    // minor version and the capability bits in one group each; the answer to
+    // Identify. Always sent without checksum, since it is what tells the host
+    // whether checksums are understood.
+    Identity {
+        model: u16,
+        major: u8,
+        minor: u8,
+        capabilities: u8,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    UnknownCommand(u8),
+    UnknownSubcommand(u8),
+    Truncated { needed: usize, available: usize },
+    MissingMarker { offset: usize, byte: u8 },
+    SecondsOutOfRange(u32),
+    InvalidDate { year: u16, month: u8, day: u8 },
+    WrongWeekday { weekday: u8, expected: u8 },
+    BadChecksum { found: u8, expected: u8 },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnknownCommand(b) => write!(f, "unknown command byte {:#04x}", b),
+            DecodeError::UnknownSubcommand(b) => write!(f, "unknown subcommand byte {:#04x}", b),
+            DecodeError::Truncated { needed, available } => {
+                write!(
+                    f,
+                    "frame truncated, needs {} bytes but {} available",
+                    needed, available
+                )
+            }
+            DecodeError::MissingMarker { offset, byte } => {
+                write!(
+                    f,
+                    "byte {:#04x} at offset {} lacks the marker bit",
+                    byte, offset
+                )
+            }
+            DecodeError::SecondsOutOfRange(seconds) => {
+                write!(f, "{} seconds is past the end of the day", seconds)
+            }
+            DecodeError::InvalidDate { year, month, day } => {
+                write!(f, "{:04}-{:02}-{:02} is not a valid date", year, month, day)
+            }
+            DecodeError::WrongWeekday { weekday, expected } => {
+                write!(
+                    f,
+                    "weekday {} does not match the date, expected {}",
+                    weekday, expected
+                )
+            }
+            DecodeError::BadChecksum { found, expected } => {
+                write!(
+                    f,
+                    "checksum {:#04x} does not match the frame, expected {:#04x}",
+                    found, expected
+                )
+            }
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+// Big-endian 7-bit groups, each with the high bit set so payload bytes can
+// never be mistaken for a command letter.
+pub fn encode_seven_bit(value: u32, groups: usize) -> Vec<u8> {
+    (0..groups)
+        .rev()
+        .map(|i| ((value >> (7 * i)) & SEVEN_BIT_MASK) as u8 | MARKER)
+        .collect()
+}
+
+// `offset` is only used for error reporting.
+pub fn decode_seven_bit(buf: &[u8], offset: usize) -> Result<u32, DecodeError> {
+    let mut value = 0;
+    for (i, &b) in buf.iter().enumerate() {
+        if b & MARKER == 0 {
+            return Err(DecodeError::MissingMarker {
+                offset: offset + i,
+                byte: b,
+            });
+        }
+        value = (value << 7) | u32::from(b & !MARKER);
+    }
+    Ok(value)
+}
+
+// Sum of the frame bytes in 7 bits, marked like a payload group.
+pub fn checksum(frame: &[u8]) -> u8 {
+    let sum = frame.iter().fold(0u32, |sum, &b| sum + u32::from(b));
+    (sum & SEVEN_BIT_MASK) as u8 | MARKER
+}
+
+// In the checksummed variant frames with a payload are followed by their
+// checksum. Commands without one stay as they are, so a one-byte commit keeps
+// its timing.
+fn with_checksum(mut buf: Vec<u8>, has_payload: bool) -> Vec<u8> {
+    if has_payload {
+        buf.push(checksum(&buf));
+    }
+    buf
+}
+
+fn take(buf: &[u8], needed: usize) -> Result<&[u8], DecodeError> {
+    buf.get(..needed).ok_or(DecodeError::Truncated {
+        needed,
+        available: buf.len(),
+    })
+}
+
+impl Frame {
+    pub fn set_time_of_day(time: impl Timelike) -> Frame {
+        Frame::SetTimeOfDay {
+            seconds: time.num_seconds_from_midnight(),
+        }
+    }
+
+    pub fn set_date(date: impl Datelike) -> Frame {
+        Frame::SetDate {
+            year: date.year().clamp(0, 0x3fff) as u16,
+            month: date.month() as u8,
+            day: date.day() as u8,
+            weekday: date.weekday().number_from_monday() as u8,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        match *self {
+            Frame::SetTimeOfDay { seconds } => {
+                let mut buf = b"Sb".to_vec();
+                buf.extend(encode_seven_bit(seconds, SECONDS_GROUPS));
+                buf
+            }
+            Frame::SetDate {
+                year,
+                month,
+                day,
+                weekday,
+            } => {
+                let mut buf = b"Sd".to_vec();
+                buf.extend(encode_seven_bit(year.into(), YEAR_GROUPS));
+                for field in [month, day, weekday] {
+                    buf.extend(encode_seven_bit(field.into(), 1));
+                }
+                buf
+            }
+            Frame::Commit => b"c".to_vec(),
+            Frame::QueryTime => b"q".to_vec(),
+            Frame::TimeReport { seconds } => {
+                let mut buf = b"Rb".to_vec();
+                buf.extend(encode_seven_bit(seconds, SECONDS_GROUPS));
+                buf
+            }
+            Frame::Identify => b"i".to_vec(),
+            Frame::Identity {
+                model,
+                major,
+                minor,
+                capabilities,
+            } => {
+                let mut buf = b"Ri".to_vec();
+                buf.extend(encode_seven_bit(model.into(), MODEL_GROUPS));
+                for field in [major, minor, capabilities] {
+                    buf.extend(encode_seven_bit(field.into(), 1));
+                }
+                buf
+            }
+        }
+    }
+
+    fn has_checksum(&self) -> bool {
+        !matches!(
+            self,
+            Frame::Commit | Frame::QueryTime | Frame::Identify | Frame::Identity { .. }
+        )
+    }
+
+    // The checksummed variant of the frame. The device's Identity answer
+    // carries none despite its payload.
+    pub fn encode_checked(&self) -> Vec<u8> {
+        with_checksum(self.encode(), self.has_checksum())
+    }
+
+    // Decodes the frame at the start of `buf`, returning it together with the
+    // number of bytes it occupied.
+    pub fn decode(buf: &[u8]) -> Result<(Frame, usize), DecodeError> {
+        match take(buf, 1)?[0] {
+            b'c' => Ok((Frame::Commit, 1)),
+            b'S' => match take(buf, 2)?[1] {
+                b'b' => {
+                    let seconds = decode_seconds(buf)?;
+                    Ok((Frame::SetTimeOfDay { seconds }, 2 + SECONDS_GROUPS))
+                }
+                b'd' => {
+                    let payload = take(&buf[2..], DATE_LEN)?;
+                    Ok((decode_date(payload)?, 2 + DATE_LEN))
+                }
+                other => Err(DecodeError::UnknownSubcommand(other)),
+            },
+            b'q' => Ok((Frame::QueryTime, 1)),
+            b'i' => Ok((Frame::Identify, 1)),
+            b'R' => match take(buf, 2)?[1] {
+                b'b' => {
+                    let seconds = decode_seconds(buf)?;
+                    Ok((Frame::TimeReport { seconds }, 2 + SECONDS_GROUPS))
+                }
+                b'i' => {
+                    let payload = take(&buf[2..], IDENTITY_LEN)?;
+                    Ok((decode_identity(payload)?, 2 + IDENTITY_LEN))
+                }
+                other => Err(DecodeError::UnknownSubcommand(other)),
+            },
+            other => Err(DecodeError::UnknownCommand(other)),
+        }
+    }
+
+    pub fn decode_checked(buf: &[u8]) -> Result<(Frame, usize), DecodeError> {
+        let (frame, len) = Frame::decode(buf)?;
+        if !frame.has_checksum() {
+            return Ok((frame, len));
+        }
+        let found = take(buf, len + 1)?[len];
+        let expected = checksum(&buf[..len]);
+        if found != expected {
+            return Err(DecodeError::BadChecksum { found, expected });
+        }
+        Ok((frame, len + 1))
+    }
+}
+
+// Seconds payload following a two-byte command.
+fn decode_seconds(buf: &[u8]) -> Result<u32, DecodeError> {
+    let seconds = decode_seven_bit(take(&buf[2..], SECONDS_GROUPS)?, 2)?;
+    if seconds >= SECONDS_PER_DAY {
+        return Err(DecodeError::SecondsOutOfRange(seconds));
+    }
+    Ok(seconds)
+}
+
+fn decode_date(payload: &[u8]) -> Result<Frame, DecodeError> {
+    let year = decode_seven_bit(&payload[..YEAR_GROUPS], 2)? as u16;
+    let month = decode_seven_bit(&payload[YEAR_GROUPS..YEAR_GROUPS + 1], 2 + YEAR_GROUPS)? as u8;
+    let day = decode_seven_bit(&payload[YEAR_GROUPS + 1..YEAR_GROUPS + 2], 3 + YEAR_GROUPS)? as u8;
+    let weekday = decode_seven_bit(&payload[YEAR_GROUPS + 2..], 4 + YEAR_GROUPS)? as u8;
+    let date = NaiveDate::from_ymd_opt(year.into(), month.into(), day.into())
+        .ok_or(DecodeError::InvalidDate { year, month, day })?;
+    let expected = date.weekday().number_from_monday() as u8;
+    if weekday != expected {
+        return Err(DecodeError::WrongWeekday { weekday, expected });
+    }
+    Ok(Frame::SetDate {
+        year,
+        month,
+        day,
+        weekday,
+    })
+}
+
+fn decode_identity(payload: &[u8]) -> Result<Frame, DecodeError> {
+    let model = decode_seven_bit(&payload[..MODEL_GROUPS], 2)? as u16;
+    let field = |i: usize| {
+        decode_seven_bit(
+            &payload[MODEL_GROUPS + i..MODEL_GROUPS + i + 1],
+            2 + MODEL_GROUPS + i,
+        )
+        .map(|value| value as u8)
+    };
+    Ok(Frame::Identity {
+        model,
+        major: field(0)?,
+        minor: field(1)?,
+        capabilities: field(2)?,
+    })
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Frame::SetTimeOfDay { seconds } => write!(
+                f,
+                "SetTimeOfDay {:02}:{:02}:{:02}",
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60
+            ),
+            Frame::SetDate {
+                year,
+                month,
+                day,
+                weekday,
+            } => write!(
+                f,
+                "SetDate {:04}-{:02}-{:02} weekday {}",
+                year, month, day, weekday
+            ),
+            Frame::Commit => write!(f, "Commit"),
+            Frame::QueryTime => write!(f, "QueryTime"),
+            Frame::TimeReport { seconds } => write!(
+                f,
+                "TimeReport {:02}:{:02}:{:02}",
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60
+            ),
+            Frame::Identify => write!(f, "Identify"),
+            Frame::Identity {
+                model,
+                major,
+                minor,
+                capabilities,
+            } => write!(
+                f,
+                "Identity model {} firmware {}.{} capabilities {:#04x}",
+                model, major, minor, capabilities
+            ),
+        }
+    }
+}
+
+// An integer argument of a raw command, as VALUE or VALUE:GROUPS. Without
+// a group count it takes as few 7-bit groups as the value needs.
+#[derive(Debug, Clone, Copy)]
+pub struct Argument {
+    pub value: u32,
+    pub groups: usize,
+}
+
+// A u32 never needs more than five groups.
+const MAX_GROUPS: usize = 5;
+
+impl FromStr for Argument {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Argument, String> {
+        let (value, groups) = match text.split_once(':') {
+            Some((value, groups)) => (value, Some(groups)),
+            None => (text, None),
+        };
+        let value: u32 = value
+            .parse()
+            .map_err(|e| format!("invalid value {:?}: {}", value, e))?;
+        let needed = (1..MAX_GROUPS)
+            .find(|&groups| value >> (7 * groups) == 0)
+            .unwrap_or(MAX_GROUPS);
+        let groups = match groups {
+            Some(groups) => groups
+                .parse()
+                .map_err(|e| format!("invalid group count {:?}: {}", groups, e))?,
+            None => needed,
+        };
+        if !(needed..=MAX_GROUPS).contains(&groups) {
+            return Err(format!(
+                "{} needs between {} and {} groups, not {}",
+                value, needed, MAX_GROUPS, groups
+            ));
+        }
+        Ok(Argument { value, groups })
+    }
+}
+
+// A frame for any device command: the command letters followed by the
+// arguments in 7-bit groups, the way the known frames are built. `checked`
+// gives the checksummed variant, where the arguments are the payload.
+pub fn command_frame(
+    command: &str,
+    args: &[Argument],
+    checked: bool,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    if command.is_empty() || !command.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(format!("Command {:?} has to be printable ASCII", command).into());
+    }
+    let mut buf = command.as_bytes().to_vec();
+    for arg in args {
+        buf.extend(encode_seven_bit(arg.value, arg.groups));
+    }
+    if checked {
+        buf = with_checksum(buf, !args.is_empty());
+    }
+    Ok(buf)
+}
+
+pub struct DecodedStream {
+    // Frames with the offset they start at.
+    pub frames: Vec<(usize, Frame)>,
+    // Where decoding stopped, if it did not reach the end.
+    pub error: Option<(usize, DecodeError)>,
+}
+
+pub fn decode_stream(buf: &[u8], checked: bool) -> DecodedStream {
+    let decode = if checked {
+        Frame::decode_checked
+    } else {
+        Frame::decode
+    };
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    while offset < buf.len() {
+        match decode(&buf[offset..]) {
+            Ok((frame, len)) => {
+                frames.push((offset, frame));
+                offset += len;
+            }
+            Err(e) => {
+                return DecodedStream {
+                    frames,
+                    error: Some((offset, e)),
+                }
+            }
+        }
+    }
+    DecodedStream {
+        frames,
+        error: None,
+    }
+}
+
+pub fn parse_hex(text: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let digits: Vec<char> = text
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':' && *c != ',')
+        .collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err("Hex input has an odd number of digits".into());
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair: String = pair.iter().collect();
+            u8::from_str_radix(&pair, 16).map_err(|_| format!("Invalid hex byte {:?}", pair).into())
+        })
+        .collect()
+}
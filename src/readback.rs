@@ -0,0 +1,72 @@
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, Timelike};
+
+use crate::protocol::{DecodeError, Frame, SECONDS_PER_DAY};
+use crate::transport::ClockTransport;
+
+pub struct Reading {
+    pub device_seconds: u32,
+    pub host_time: DateTime<Local>,
+    // Device time minus host time.
+    pub offset: chrono::Duration,
+}
+
+// The device only reports whole seconds, so its time is taken to be the
+// middle of the reported second; the offset is accurate to about half a
+// second plus the link latency.
+fn offset(device_seconds: u32, host_time: DateTime<Local>) -> chrono::Duration {
+    let host_nanos = i64::from(host_time.num_seconds_from_midnight()) * 1_000_000_000
+        + i64::from(host_time.nanosecond().min(999_999_999));
+    let device_nanos = i64::from(device_seconds) * 1_000_000_000 + 500_000_000;
+    let day = i64::from(SECONDS_PER_DAY) * 1_000_000_000;
+    // Wrap into [-12h, 12h) so a reading across midnight is not a day off.
+    let diff = (device_nanos - host_nanos).rem_euclid(day);
+    let diff = if diff >= day / 2 { diff - day } else { diff };
+    chrono::Duration::nanoseconds(diff)
+}
+
+// `checked` expects the report in the checksummed frame variant.
+pub fn read_time(
+    transport: &mut dyn ClockTransport,
+    timeout: Duration,
+    checked: bool,
+) -> Result<Reading, Box<dyn Error>> {
+    let decode = if checked {
+        Frame::decode_checked
+    } else {
+        Frame::decode
+    };
+    let sent_at = Local::now();
+    transport.send(&Frame::QueryTime.encode())?;
+    let deadline = Instant::now() + timeout;
+    let mut reply = Vec::new();
+    let mut buf = [0u8; 64];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err("Device did not answer the time query".into());
+        }
+        let len = transport.receive(&mut buf, remaining)?;
+        reply.extend_from_slice(&buf[..len]);
+        match decode(&reply) {
+            Ok((Frame::TimeReport { seconds }, _)) => {
+                let received_at = Local::now();
+                let host_time = sent_at + (received_at - sent_at) / 2;
+                return Ok(Reading {
+                    device_seconds: seconds,
+                    host_time,
+                    offset: offset(seconds, host_time),
+                });
+            }
+            Ok((frame, _)) => {
+                return Err(format!("Unexpected reply to time query: {}", frame).into())
+            }
+            Err(DecodeError::Truncated { .. }) => {}
+            Err(e) => {
+                return Err(format!("Invalid reply to time query {:02x?}: {}", reply, e).into())
+            }
+        }
+    }
+}
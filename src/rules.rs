@@ -0,0 +1,121 @@
+use std::fmt;
+
+use log::{debug, info};
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer};
+
+use crate::discovery::PortInfo;
+
+#[derive(Clone)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    fn is_match(&self, value: &str) -> bool {
+        self.0.is_match(value)
+    }
+}
+
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        // Patterns must match the whole attribute, not a substring of it.
+        Regex::new(&format!("^(?:{})$", pattern))
+            .map(Pattern)
+            .map_err(de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MatchRule {
+    pub name: String,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub manufacturer: Option<Pattern>,
+    pub product: Option<Pattern>,
+    pub serial_number: Option<Pattern>,
+    pub location: Option<Pattern>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchedPort {
+    pub port: PortInfo,
+    pub rule: String,
+}
+
+fn check_id(field: &str, expected: Option<u16>, actual: Option<u16>) -> Result<(), String> {
+    match (expected, actual) {
+        (None, _) => Ok(()),
+        (Some(expected), Some(actual)) if expected == actual => Ok(()),
+        (Some(expected), actual) => Err(format!("{} {:04x?} != {:04x}", field, actual, expected)),
+    }
+}
+
+fn check_pattern(
+    field: &str,
+    expected: &Option<Pattern>,
+    actual: &Option<String>,
+) -> Result<(), String> {
+    match (expected, actual) {
+        (None, _) => Ok(()),
+        (Some(expected), Some(actual)) if expected.is_match(actual) => Ok(()),
+        (Some(expected), actual) => Err(format!(
+            "{} {:?} does not match {:?}",
+            field, actual, expected
+        )),
+    }
+}
+
+impl MatchRule {
+    pub fn defaults() -> Vec<MatchRule> {
+        vec![MatchRule {
+            name: "ch340".into(),
+            vid: Some(0x1a86),
+            pid: Some(0x7523),
+            manufacturer: None,
+            product: None,
+            serial_number: None,
+            location: None,
+        }]
+    }
+
+    pub fn check(&self, port: &PortInfo) -> Result<(), String> {
+        check_id("vid", self.vid, port.vid)?;
+        check_id("pid", self.pid, port.pid)?;
+        check_pattern("manufacturer", &self.manufacturer, &port.manufacturer)?;
+        check_pattern("product", &self.product, &port.product)?;
+        check_pattern("serial_number", &self.serial_number, &port.serial_number)?;
+        check_pattern("location", &self.location, &port.location)?;
+        Ok(())
+    }
+}
+
+pub fn match_ports(ports: Vec<PortInfo>, rules: &[MatchRule]) -> Vec<MatchedPort> {
+    let mut matched = Vec::new();
+    'ports: for port in ports {
+        for rule in rules {
+            match rule.check(&port) {
+                Ok(()) => {
+                    info!("Port {} matched rule {}", port.name, rule.name);
+                    matched.push(MatchedPort {
+                        port,
+                        rule: rule.name.clone(),
+                    });
+                    continue 'ports;
+                }
+                Err(reason) => debug!(
+                    "Port {} rejected by rule {}: {}",
+                    port.name, rule.name, reason
+                ),
+            }
+        }
+        info!("Port {} matched no rule", port.name);
+    }
+    matched
+}
@@ -0,0 +1,128 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use log::warn;
+
+// A wall clock drifting this far from the monotonic one counts as stepped
+// even where the kernel does not report steps; NTP slewing stays far below.
+const STEP_THRESHOLD: Duration = Duration::from_millis(2);
+
+// Ties wall-clock times to the monotonic clock at one moment, so deadlines
+// keep their meaning if the system clock is stepped, and notices when it is.
+pub struct Schedule {
+    wall: DateTime<Local>,
+    monotonic: Instant,
+    #[cfg(target_os = "linux")]
+    detector: Option<linux::StepDetector>,
+}
+
+impl Schedule {
+    pub fn start() -> Schedule {
+        #[cfg(target_os = "linux")]
+        let detector = linux::StepDetector::new()
+            .map_err(|e| warn!("Cannot watch for clock steps: {}", e))
+            .ok();
+        Schedule {
+            wall: Local::now(),
+            monotonic: Instant::now(),
+            #[cfg(target_os = "linux")]
+            detector,
+        }
+    }
+
+    // The wall time the schedule started at.
+    pub fn started(&self) -> DateTime<Local> {
+        self.wall
+    }
+
+    pub fn elapsed(&self) -> chrono::Duration {
+        Schedule::offset(Instant::now(), self.monotonic)
+    }
+
+    pub fn instant_at(&self, time: DateTime<Local>) -> Instant {
+        let offset = time - self.wall;
+        match offset.to_std() {
+            Ok(ahead) => self.monotonic + ahead,
+            Err(_) => self
+                .monotonic
+                .checked_sub((-offset).to_std().unwrap_or_default())
+                .unwrap_or(self.monotonic),
+        }
+    }
+
+    // How far `instant` is past `deadline`, negative if it is before.
+    pub fn offset(instant: Instant, deadline: Instant) -> chrono::Duration {
+        let signed =
+            |d: Duration| chrono::Duration::nanoseconds(d.as_nanos().min(i64::MAX as u128) as i64);
+        match instant.checked_duration_since(deadline) {
+            Some(late) => signed(late),
+            None => -signed(deadline - instant),
+        }
+    }
+
+    // How far the wall clock has moved against the monotonic one, if it was
+    // stepped since the schedule started.
+    pub fn stepped(&mut self) -> Option<chrono::Duration> {
+        let now = Instant::now();
+        let drift = Local::now() - (self.wall + Schedule::offset(now, self.monotonic));
+        #[cfg(target_os = "linux")]
+        let reported = self.detector.as_mut().is_some_and(|d| d.fired());
+        #[cfg(not(target_os = "linux"))]
+        let reported = false;
+        let threshold =
+            chrono::Duration::from_std(STEP_THRESHOLD).unwrap_or_else(|_| chrono::Duration::zero());
+        (reported || drift > threshold || drift < -threshold).then_some(drift)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::os::unix::io::FromRawFd;
+
+    use chrono::Local;
+
+    const ARMED_FOR_SECONDS: i64 = 365 * 24 * 60 * 60;
+
+    // A realtime timer far in the future with TFD_TIMER_CANCEL_ON_SET: reads
+    // fail with ECANCELED once anyone sets the system clock.
+    pub struct StepDetector {
+        timer: File,
+    }
+
+    impl StepDetector {
+        pub fn new() -> io::Result<StepDetector> {
+            let fd = unsafe {
+                libc::timerfd_create(libc::CLOCK_REALTIME, libc::TFD_CLOEXEC | libc::TFD_NONBLOCK)
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let timer = unsafe { File::from_raw_fd(fd) };
+            let spec = libc::itimerspec {
+                it_interval: libc::timespec {
+                    tv_sec: 0,
+                    tv_nsec: 0,
+                },
+                it_value: libc::timespec {
+                    tv_sec: (Local::now().timestamp() + ARMED_FOR_SECONDS) as libc::time_t,
+                    tv_nsec: 0,
+                },
+            };
+            let flags = libc::TFD_TIMER_ABSTIME | libc::TFD_TIMER_CANCEL_ON_SET;
+            if unsafe { libc::timerfd_settime(fd, flags, &spec, std::ptr::null_mut()) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(StepDetector { timer })
+        }
+
+        pub fn fired(&mut self) -> bool {
+            let mut expirations = [0u8; 8];
+            match self.timer.read_exact(&mut expirations) {
+                Err(e) => e.raw_os_error() == Some(libc::ECANCELED),
+                Ok(_) => false,
+            }
+        }
+    }
+}
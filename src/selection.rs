@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::device::DeviceConfig;
+use crate::rules::MatchedPort;
+
+#[derive(Debug, Clone)]
+pub enum Selector {
+    Port(String),
+    SerialNumber(String),
+    Location(String),
+    Alias(String),
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Selector::Port(name) => write!(f, "port {}", name),
+            Selector::SerialNumber(serial) => write!(f, "serial number {}", serial),
+            Selector::Location(location) => write!(f, "location {}", location),
+            Selector::Alias(alias) => write!(f, "alias {}", alias),
+        }
+    }
+}
+
+impl Selector {
+    pub fn resolve(
+        &self,
+        devices: &BTreeMap<String, DeviceConfig>,
+    ) -> Result<DeviceConfig, Box<dyn Error>> {
+        let identity = match self {
+            Selector::Port(name) => DeviceConfig {
+                port: Some(name.clone()),
+                ..Default::default()
+            },
+            Selector::SerialNumber(serial) => DeviceConfig {
+                serial_number: Some(serial.clone()),
+                ..Default::default()
+            },
+            Selector::Location(location) => DeviceConfig {
+                location: Some(location.clone()),
+                ..Default::default()
+            },
+            Selector::Alias(name) => devices
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("Unknown device alias {}", name))?,
+        };
+        Ok(identity)
+    }
+}
+
+fn describe(ports: &[MatchedPort]) -> String {
+    ports
+        .iter()
+        .map(|matched| {
+            format!(
+                "{} (serial {}, location {})",
+                matched.port.name,
+                matched.port.serial_number.as_deref().unwrap_or("-"),
+                matched.port.location.as_deref().unwrap_or("-")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub fn select_port(
+    ports: Vec<MatchedPort>,
+    selector: Option<&Selector>,
+    devices: &BTreeMap<String, DeviceConfig>,
+) -> Result<MatchedPort, Box<dyn Error>> {
+    let candidates: Vec<MatchedPort> = match selector {
+        Some(selector) => {
+            let identity = selector.resolve(devices)?;
+            let candidates: Vec<MatchedPort> = ports
+                .into_iter()
+                .filter(|p| identity.matches(&p.port))
+                .collect();
+            if candidates.is_empty() {
+                return Err(format!("No matching serial port with {}", selector).into());
+            }
+            candidates
+        }
+        None => ports,
+    };
+
+    if candidates.is_empty() {
+        return Err("No serial ports found".into());
+    }
+
+    if candidates.len() > 1 {
+        return Err(format!(
+            "Multiple serial ports found, select one by serial number, location or alias: {}",
+            describe(&candidates)
+        )
+        .into());
+    }
+
+    Ok(candidates.into_iter().next().unwrap())
+}
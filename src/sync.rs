@@ -0,0 +1,850 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Instant;
+
+use chrono::{DateTime, Duration, Local, Offset, TimeZone, Timelike};
+use log::{debug, error, info, warn};
+
+use crate::device::Target;
+use crate::handshake::{self, Answer, SendError};
+use crate::identify;
+use crate::lock::{self, DeviceLock};
+use crate::probe;
+use crate::profile::DeviceProfile;
+use crate::readback;
+use crate::schedule::Schedule;
+use crate::transport::{self, ClockTransport, Trace};
+use crate::wait::{self, WaitStrategy};
+
+pub struct SyncReport {
+    pub port: String,
+    // The offset measured after the sync, for devices that support readback.
+    pub result: Result<Option<Duration>, Box<dyn Error>>,
+    // When the commit was written relative to when it was due.
+    pub commit_offset: Option<Duration>,
+}
+
+pub struct SyncRun {
+    pub sync_time: DateTime<Local>,
+    pub reports: Vec<SyncReport>,
+}
+
+const READBACK_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(500);
+const IDENTIFY_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(500);
+
+pub struct SyncOptions {
+    pub lock_timeout: Option<std::time::Duration>,
+    // Largest offset a device may read back after the sync.
+    pub tolerance: Duration,
+    // Boundaries tried before a run is given up.
+    pub attempts: u32,
+    pub trace: Option<Trace>,
+    pub wait: WaitStrategy,
+}
+
+impl Default for SyncOptions {
+    fn default() -> SyncOptions {
+        SyncOptions {
+            lock_timeout: None,
+            tolerance: Duration::seconds(1),
+            attempts: 3,
+            trace: None,
+            wait: WaitStrategy::default(),
+        }
+    }
+}
+
+pub struct Clock {
+    pub port: String,
+    pub profile: DeviceProfile,
+    pub transport: Result<Box<dyn ClockTransport>, Box<dyn Error>>,
+}
+
+// The start of the second `time` falls in. Taken off the instant rather than
+// rebuilt from local fields, which may not exist or be ambiguous around a
+// DST change.
+fn time_trunc_second<Tz: TimeZone>(time: &DateTime<Tz>) -> DateTime<Tz> {
+    time.clone() - Duration::nanoseconds(i64::from(time.nanosecond()))
+}
+
+// Whether the local date or UTC offset differs between the two times.
+fn crosses_change<Tz: TimeZone>(earlier: &DateTime<Tz>, later: &DateTime<Tz>) -> bool {
+    earlier.date_naive() != later.date_naive() || earlier.offset().fix() != later.offset().fix()
+}
+
+enum Boundary<Tz: TimeZone> {
+    // The boundary to sync to and how far away it is.
+    At(DateTime<Tz>, Duration),
+    // Every usable boundary lies past a change of date or UTC offset at this
+    // time, so preloading has to wait until it is over.
+    After(DateTime<Tz>),
+}
+
+// The first second boundary more than `lead` away. Preload and commit must
+// not straddle midnight or a UTC offset change: seconds of day jump there,
+// and a device given only the time of day would keep the old date.
+fn next_sync_time<Tz: TimeZone>(now: DateTime<Tz>, lead: Duration) -> Boundary<Tz> {
+    let lead = lead.max(Duration::microseconds(100));
+    let mut next = time_trunc_second(&now);
+    loop {
+        next += Duration::seconds(1);
+        if crosses_change(&now, &next) {
+            return Boundary::After(next);
+        }
+        let dist = next.clone() - now.clone();
+        if dist > lead {
+            return Boundary::At(next, dist);
+        }
+    }
+}
+
+// The returned locks have to be held until the clocks are done with.
+pub fn open_clocks(targets: &[Target], options: &SyncOptions) -> (Vec<Clock>, Vec<DeviceLock>) {
+    let mut locks = Vec::new();
+    let clocks = targets
+        .iter()
+        .map(|target| {
+            let mut transport =
+                lock::acquire(&target.identity, options.lock_timeout).and_then(|lock| {
+                    locks.push(lock);
+                    let transport = probe::open_device(target, options.trace.as_ref())?;
+                    Ok(match &target.calibration {
+                        Some(calibration) => {
+                            debug!(
+                                "{}: using calibrated latency of {} µs, jitter {} µs",
+                                target.name, calibration.median_us, calibration.jitter_us
+                            );
+                            transport::with_latency(transport, calibration.latency())
+                        }
+                        None => transport,
+                    })
+                });
+            let mut profile = target.profile.clone();
+            if let (true, Ok(transport)) = (target.device.identify, &mut transport) {
+                match identify::identify(transport.as_mut(), IDENTIFY_TIMEOUT) {
+                    Ok(identity) => {
+                        info!("{}: {}", target.name, identity);
+                        profile = identity.profile(target);
+                    }
+                    Err(e) => warn!(
+                        "{}: identification failed, using the configured profile: {}",
+                        target.name, e
+                    ),
+                }
+            }
+            Clock {
+                port: target.name.clone(),
+                profile,
+                transport,
+            }
+        })
+        .collect();
+    (clocks, locks)
+}
+
+pub fn sync_targets(targets: &[Target], options: &SyncOptions) -> Result<SyncRun, Box<dyn Error>> {
+    let (clocks, _locks) = open_clocks(targets, options);
+    sync_clocks(clocks, options)
+}
+
+pub fn within_tolerance(offset: Duration, tolerance: Duration) -> bool {
+    offset <= tolerance && offset >= -tolerance
+}
+
+pub fn read_offset(
+    port: &str,
+    transport: &mut dyn ClockTransport,
+    checked: bool,
+    when: &str,
+) -> Result<Duration, Box<dyn Error>> {
+    let reading = readback::read_time(transport, READBACK_TIMEOUT, checked)?;
+    let seconds = reading.device_seconds;
+    info!(
+        "{}: {} device reads {:02}:{:02}:{:02} at host time {}, offset {} ms",
+        port,
+        when,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        reading.host_time.format("%H:%M:%S%.3f"),
+        reading.offset.num_milliseconds()
+    );
+    Ok(reading.offset)
+}
+
+// Why an attempt at a boundary was given up.
+enum Slip {
+    Unacknowledged { port: String, error: SendError },
+    Late { late_by: Duration },
+    ClockStepped { step: Duration },
+}
+
+impl fmt::Display for Slip {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Slip::Unacknowledged { port, error } => write!(f, "{}: {}", port, error),
+            Slip::Late { late_by } => write!(
+                f,
+                "preload finished {} µs after the first commit was due",
+                late_by.num_microseconds().unwrap_or(i64::MAX)
+            ),
+            Slip::ClockStepped { step } => write!(
+                f,
+                "system clock stepped by {} µs",
+                step.num_microseconds().unwrap_or(i64::MAX)
+            ),
+        }
+    }
+}
+
+// Sends every preload for `next_sync_time`. Fails if a preload could not be
+// delivered, or the commits could no longer go out, in time for the boundary.
+fn preload(
+    clocks: &mut [Clock],
+    schedule: &Schedule,
+    next_sync_time: DateTime<Local>,
+    latency: impl Fn(&Clock) -> Duration,
+) -> Result<(), Slip> {
+    for clock in clocks.iter_mut() {
+        // Profiles without a commit frame act on the preload itself, which
+        // is then held back until the boundary.
+        if clock.profile.commit_frame().is_empty() {
+            continue;
+        }
+        let deadline = schedule.instant_at(next_sync_time - latency(clock));
+        let ack_timeout = clock.profile.ack_timeout();
+        if let Ok(transport) = &mut clock.transport {
+            if ack_timeout.is_some() {
+                if let Err(e) = handshake::drain(transport.as_mut()) {
+                    clock.transport = Err(e.into());
+                    continue;
+                }
+            }
+            for frame in clock.profile.preload_frames(&next_sync_time) {
+                match handshake::send_frame(transport.as_mut(), &frame, ack_timeout, deadline) {
+                    Ok(()) => {}
+                    Err(SendError::Io(e)) => {
+                        clock.transport = Err(e.into());
+                        break;
+                    }
+                    Err(error) => {
+                        return Err(Slip::Unacknowledged {
+                            port: clock.port.clone(),
+                            error,
+                        })
+                    }
+                }
+            }
+        }
+    }
+    // A clock that failed above no longer needs its commit, however far away
+    // it is.
+    let first_commit = next_sync_time
+        - clocks
+            .iter()
+            .filter(|clock| clock.transport.is_ok())
+            .map(&latency)
+            .max()
+            .unwrap_or_else(Duration::zero);
+    let late_by = Schedule::offset(Instant::now(), schedule.instant_at(first_commit));
+    if late_by > Duration::zero() {
+        return Err(Slip::Late { late_by });
+    }
+    for clock in clocks.iter_mut() {
+        if let (Some(_), Ok(transport)) = (clock.profile.ack_timeout(), &mut clock.transport) {
+            if let Err(e) = handshake::drain(transport.as_mut()) {
+                clock.transport = Err(e.into());
+            }
+        }
+    }
+    Ok(())
+}
+
+// Writes every commit at its scheduled time and returns how far from it each
+// one went out. Fails if the system clock was stepped before the last one,
+// since the commits no longer land on next_sync_time.
+fn commit(
+    clocks: &mut [Clock],
+    schedule: &mut Schedule,
+    next_sync_time: DateTime<Local>,
+    latency: impl Fn(&Clock) -> Duration,
+    strategy: WaitStrategy,
+) -> Result<Vec<Option<Duration>>, Slip> {
+    let mut commit_offsets = vec![None; clocks.len()];
+    for (clock, commit_offset) in clocks.iter_mut().zip(&mut commit_offsets) {
+        let commit_time = schedule.instant_at(next_sync_time - latency(clock));
+        let mut commit = clock.profile.commit_frame();
+        if commit.is_empty() {
+            commit = clock.profile.preload_frames(&next_sync_time).concat();
+        }
+        if let Ok(transport) = &mut clock.transport {
+            if let Err(e) = wait::wait_until(strategy, commit_time) {
+                clock.transport = Err(e);
+                continue;
+            }
+            if let Some(step) = schedule.stepped() {
+                return Err(Slip::ClockStepped { step });
+            }
+            let written = Instant::now();
+            if let Err(e) = transport.send(&commit) {
+                clock.transport = Err(e.into());
+                continue;
+            }
+            let offset = Schedule::offset(written, commit_time);
+            info!(
+                "{}: commit written {:+} µs from its scheduled time ({:?} wait)",
+                clock.port,
+                offset.num_microseconds().unwrap_or(i64::MAX),
+                strategy
+            );
+            *commit_offset = Some(offset);
+        }
+    }
+    Ok(commit_offsets)
+}
+
+// Preloads every clock with the same boundary and commits them together, so
+// one clock failing does not keep the others from being set.
+pub fn sync_clocks(
+    mut clocks: Vec<Clock>,
+    options: &SyncOptions,
+) -> Result<SyncRun, Box<dyn Error>> {
+    for clock in &mut clocks {
+        if let (true, Ok(transport)) = (clock.profile.readback, &mut clock.transport) {
+            if let Err(e) = read_offset(
+                &clock.port,
+                transport.as_mut(),
+                clock.profile.checksum,
+                "before sync",
+            ) {
+                warn!(
+                    "{}: failed to read device time before sync: {}",
+                    clock.port, e
+                );
+            }
+        }
+    }
+
+    // Clocks further away get their commit earlier so that all of them
+    // act on it at next_sync_time. Latency covers the first byte of the
+    // commit; the rest of it still has to go over the line.
+    let frame_time = |clock: &Clock, transport: &dyn ClockTransport| {
+        transport.char_time() * clock.profile.commit_len().saturating_sub(1) as u32
+    };
+    let latency = |clock: &Clock| match &clock.transport {
+        Ok(transport) => Duration::from_std(
+            transport.latency() + frame_time(clock, transport.as_ref()) + clock.profile.latency(),
+        )
+        .unwrap_or_else(|_| Duration::zero()),
+        Err(_) => Duration::zero(),
+    };
+    clocks.sort_by_key(|clock| std::cmp::Reverse(latency(clock)));
+    for clock in &clocks {
+        if let Ok(transport) = &clock.transport {
+            info!(
+                "{}: committing {} µs early for {} µs link, {} µs frame and {} µs device latency",
+                clock.port,
+                latency(clock).num_microseconds().unwrap_or(i64::MAX),
+                transport.latency().as_micros(),
+                frame_time(clock, transport.as_ref()).as_micros(),
+                clock.profile.latency().as_micros()
+            );
+        }
+    }
+
+    // Each boundary has to leave room for the earliest commit; after a slip
+    // also for the preload, as long as it took last time.
+    let max_latency = clocks.first().map(latency).unwrap_or_else(Duration::zero);
+    let mut lead = max_latency;
+    let mut attempt = 1;
+    let (next_sync_time, commit_offsets) = loop {
+        let mut schedule = Schedule::start();
+        let (next_sync_time, dist) = match next_sync_time(schedule.started(), lead) {
+            Boundary::At(time, dist) => (time, dist),
+            Boundary::After(change) => {
+                info!("Waiting for the date or UTC offset change at {}", change);
+                wait::wait_until(options.wait, schedule.instant_at(change))?;
+                continue;
+            }
+        };
+        if let Some(trace) = &options.trace {
+            trace.boundary(next_sync_time);
+        }
+        let result = preload(&mut clocks, &schedule, next_sync_time, latency).and_then(|()| {
+            commit(
+                &mut clocks,
+                &mut schedule,
+                next_sync_time,
+                latency,
+                options.wait,
+            )
+        });
+        match result {
+            Ok(commit_offsets) => break (next_sync_time, commit_offsets),
+            Err(slip) if attempt < options.attempts => {
+                warn!(
+                    "Attempt {} of {} to sync to {} slipped: {}; rescheduling",
+                    attempt, options.attempts, next_sync_time, slip
+                );
+                // Only a late preload says anything about how long the next
+                // one needs.
+                if let Slip::Late { .. } = slip {
+                    lead = schedule.elapsed() + max_latency;
+                }
+                attempt += 1;
+            }
+            // Out of attempts, a clock that still does not acknowledge its
+            // preload is given up. The boundary it cost is tried again
+            // without it.
+            Err(Slip::Unacknowledged { port, error }) => {
+                error!("Giving up on {}: {}", port, error);
+                if let Some(clock) = clocks.iter_mut().find(|clock| clock.port == port) {
+                    clock.transport = Err(error.to_string().into());
+                }
+            }
+            Err(slip) => {
+                error!(
+                    "Attempt {} of {} to sync to {} slipped: {}",
+                    attempt, options.attempts, next_sync_time, slip
+                );
+                error!("Failed to finish operation within {:?}", dist);
+                return Err("Failed to finish operation.".into());
+            }
+        }
+    };
+    if attempt > 1 {
+        info!("Synced to {} on attempt {}", next_sync_time, attempt);
+    }
+
+    // Commits are acknowledged only once all of them are out, so waiting for
+    // one device does not delay the next.
+    for clock in &mut clocks {
+        if let (Some(timeout), Ok(transport)) = (clock.profile.ack_timeout(), &mut clock.transport)
+        {
+            match handshake::await_answer(transport.as_mut(), timeout) {
+                Ok(Answer::Ack) => {}
+                Ok(Answer::Nak) => clock.transport = Err("device rejected the commit".into()),
+                Ok(Answer::Silent) => {
+                    clock.transport = Err("device did not acknowledge the commit".into())
+                }
+                Err(e) => clock.transport = Err(e.into()),
+            }
+        }
+    }
+
+    info!("Sync finished to time {}", next_sync_time);
+
+    let reports = clocks
+        .into_iter()
+        .zip(commit_offsets)
+        .map(|(clock, commit_offset)| {
+            let Clock {
+                port,
+                profile,
+                transport,
+            } = clock;
+            let result = transport.and_then(|mut transport| {
+                if !profile.readback {
+                    return Ok(None);
+                }
+                let offset =
+                    read_offset(&port, transport.as_mut(), profile.checksum, "after sync")?;
+                if !within_tolerance(offset, options.tolerance) {
+                    return Err(format!(
+                        "offset {} ms after sync exceeds tolerance of {} ms",
+                        offset.num_milliseconds(),
+                        options.tolerance.num_milliseconds()
+                    )
+                    .into());
+                }
+                Ok(Some(offset))
+            });
+            SyncReport {
+                port,
+                result,
+                commit_offset,
+            }
+        })
+        .collect();
+
+    Ok(SyncRun {
+        sync_time: next_sync_time,
+        reports,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, LocalResult, NaiveDate, NaiveDateTime};
+
+    use super::*;
+    use crate::protocol::Frame;
+    use crate::transport::MemoryTransport;
+
+    // How late a commit may be written, for scheduling jitter on a busy
+    // machine.
+    const SLACK_MS: i64 = 20;
+
+    fn clock(port: &str, profile: DeviceProfile, transport: Box<dyn ClockTransport>) -> Clock {
+        Clock {
+            port: port.to_string(),
+            profile,
+            transport: Ok(transport),
+        }
+    }
+
+    fn assert_near(actual: DateTime<Local>, expected: DateTime<Local>) {
+        let late = actual - expected;
+        assert!(
+            late > -Duration::milliseconds(1) && late < Duration::milliseconds(SLACK_MS),
+            "written at {}, due at {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn preloads_and_commits_on_the_boundary() {
+        let recorder = MemoryTransport::new();
+        let clocks = vec![clock(
+            "memory",
+            DeviceProfile::default(),
+            Box::new(recorder.clone()),
+        )];
+        let run = sync_clocks(clocks, &SyncOptions::default()).unwrap();
+
+        assert_eq!(run.sync_time.nanosecond(), 0);
+        let records = recorder.records();
+        let sent: Vec<Vec<u8>> = records.iter().map(|r| r.data.clone()).collect();
+        assert_eq!(
+            sent,
+            [
+                Frame::set_time_of_day(run.sync_time).encode(),
+                Frame::set_date(run.sync_time).encode(),
+                Frame::Commit.encode(),
+            ]
+        );
+        assert!(sent[0].starts_with(b"Sb") && sent[1].starts_with(b"Sd"));
+        assert!(records[1].wall < run.sync_time);
+        assert_near(records[2].wall, run.sync_time);
+
+        let report = &run.reports[0];
+        assert!(matches!(report.result, Ok(None)));
+        assert!(report.commit_offset.is_some());
+    }
+
+    // A recorder behind a link that takes `latency` to reach the device.
+    struct Remote {
+        recorder: MemoryTransport,
+        latency: std::time::Duration,
+    }
+
+    impl ClockTransport for Remote {
+        fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+            self.recorder.send(data)
+        }
+
+        fn receive(
+            &mut self,
+            buf: &mut [u8],
+            timeout: std::time::Duration,
+        ) -> std::io::Result<usize> {
+            self.recorder.receive(buf, timeout)
+        }
+
+        fn latency(&self) -> std::time::Duration {
+            self.latency
+        }
+    }
+
+    #[test]
+    fn commits_early_by_the_link_latency() {
+        let near = MemoryTransport::new();
+        let far = MemoryTransport::new();
+        let remote = Remote {
+            recorder: far.clone(),
+            latency: std::time::Duration::from_millis(30),
+        };
+        let clocks = vec![
+            clock("near", DeviceProfile::default(), Box::new(near.clone())),
+            clock("far", DeviceProfile::default(), Box::new(remote)),
+        ];
+        let run = sync_clocks(clocks, &SyncOptions::default()).unwrap();
+
+        // The far clock is committed first, early by its latency.
+        assert_eq!(run.reports[0].port, "far");
+        let commit = |recorder: &MemoryTransport| recorder.records().last().unwrap().wall;
+        assert_near(commit(&far), run.sync_time - Duration::milliseconds(30));
+        assert_near(commit(&near), run.sync_time);
+    }
+
+    // A recorder behind a line that takes `char_time` for each byte.
+    struct Line {
+        recorder: MemoryTransport,
+        char_time: std::time::Duration,
+    }
+
+    impl ClockTransport for Line {
+        fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+            self.recorder.send(data)
+        }
+
+        fn receive(
+            &mut self,
+            buf: &mut [u8],
+            timeout: std::time::Duration,
+        ) -> std::io::Result<usize> {
+            self.recorder.receive(buf, timeout)
+        }
+
+        fn char_time(&self) -> std::time::Duration {
+            self.char_time
+        }
+    }
+
+    #[test]
+    fn commits_early_by_the_time_the_whole_frame_takes() {
+        let recorder = MemoryTransport::new();
+        let profile: DeviceProfile = toml::from_str(
+            r#"
+            preload = [
+              { literal = "T" },
+              { field = "hour", encoding = "ascii" },
+              { literal = ":" },
+              { field = "minute", encoding = "ascii" },
+              { literal = ":" },
+              { field = "second", encoding = "ascii" },
+              { literal = "\r\n" },
+            ]
+            "#,
+        )
+        .unwrap();
+        let line = Line {
+            recorder: recorder.clone(),
+            char_time: std::time::Duration::from_millis(3),
+        };
+        let run = sync_clocks(
+            vec![clock("ascii", profile, Box::new(line))],
+            &SyncOptions::default(),
+        )
+        .unwrap();
+
+        // Sent in one piece at the boundary, early by all but its first byte.
+        let records = recorder.records();
+        assert_eq!(records.len(), 1);
+        let expected = format!("T{}\r\n", run.sync_time.format("%H:%M:%S"));
+        assert_eq!(records[0].data, expected.as_bytes());
+        assert_near(records[0].wall, run.sync_time - Duration::milliseconds(30));
+    }
+
+    #[test]
+    fn preload_encodes_seconds_of_day_in_seven_bit_groups() {
+        let time = Local.with_ymd_and_hms(2026, 10, 16, 1, 2, 3).unwrap();
+        assert_eq!(Frame::set_time_of_day(time).encode(), b"Sb\x80\x80\x9d\x8b");
+    }
+
+    #[test]
+    fn unacknowledged_clock_does_not_hold_back_the_rest() {
+        let silent = MemoryTransport::new();
+        let good = MemoryTransport::new();
+        let clocks = vec![
+            clock(
+                "silent",
+                DeviceProfile {
+                    ack: true,
+                    ..DeviceProfile::default()
+                },
+                Box::new(silent.clone()),
+            ),
+            clock("good", DeviceProfile::default(), Box::new(good.clone())),
+        ];
+        let options = SyncOptions {
+            attempts: 2,
+            ..SyncOptions::default()
+        };
+        let run = sync_clocks(clocks, &options).unwrap();
+
+        // The silent clock is preloaded again on every boundary until the
+        // attempts run out, and only then given up.
+        let mut preloads: Vec<Vec<u8>> = silent
+            .records()
+            .into_iter()
+            .map(|r| r.data)
+            .filter(|data| data.starts_with(b"Sb"))
+            .collect();
+        preloads.dedup();
+        assert_eq!(preloads.len(), options.attempts as usize);
+        let silent_report = &run.reports[0];
+        let error = silent_report.result.as_ref().unwrap_err().to_string();
+        assert!(error.contains("not acknowledged"), "{}", error);
+        assert!(silent_report.commit_offset.is_none());
+        assert!(!silent.records().iter().any(|r| r.data == b"c"));
+
+        assert!(matches!(run.reports[1].result, Ok(None)));
+        assert_near(good.records().last().unwrap().wall, run.sync_time);
+    }
+
+    // A zone switching from `before` to `after` seconds east of UTC at the
+    // UTC timestamp `change`.
+    #[derive(Debug, Clone, Copy)]
+    struct Rule {
+        change: i64,
+        before: i32,
+        after: i32,
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct RuleOffset {
+        rule: Rule,
+        fixed: FixedOffset,
+    }
+
+    impl Offset for RuleOffset {
+        fn fix(&self) -> FixedOffset {
+            self.fixed
+        }
+    }
+
+    impl Rule {
+        // Central European summer time starting and ending in 2026.
+        const SPRING: Rule = Rule {
+            change: 1774746000,
+            before: 3600,
+            after: 7200,
+        };
+        const FALL: Rule = Rule {
+            change: 1792890000,
+            before: 7200,
+            after: 3600,
+        };
+
+        fn offset(&self, seconds: i32) -> RuleOffset {
+            RuleOffset {
+                rule: *self,
+                fixed: FixedOffset::east_opt(seconds).unwrap(),
+            }
+        }
+    }
+
+    impl TimeZone for Rule {
+        type Offset = RuleOffset;
+
+        fn from_offset(offset: &RuleOffset) -> Rule {
+            offset.rule
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<RuleOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<RuleOffset> {
+            let local = local.timestamp();
+            let before = local - i64::from(self.before) < self.change;
+            let after = local - i64::from(self.after) >= self.change;
+            match (before, after) {
+                (true, true) => {
+                    LocalResult::Ambiguous(self.offset(self.before), self.offset(self.after))
+                }
+                (true, false) => LocalResult::Single(self.offset(self.before)),
+                (false, true) => LocalResult::Single(self.offset(self.after)),
+                (false, false) => LocalResult::None,
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> RuleOffset {
+            self.offset_from_utc_datetime(&utc.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> RuleOffset {
+            if utc.timestamp() < self.change {
+                self.offset(self.before)
+            } else {
+                self.offset(self.after)
+            }
+        }
+    }
+
+    fn local(
+        offset: i32,
+        (year, month, day): (i32, u32, u32),
+        (hour, min, sec, milli): (u32, u32, u32, u32),
+    ) -> DateTime<FixedOffset> {
+        FixedOffset::east_opt(offset)
+            .unwrap()
+            .with_ymd_and_hms(year, month, day, hour, min, sec)
+            .unwrap()
+            + Duration::milliseconds(i64::from(milli))
+    }
+
+    fn at<Tz: TimeZone>(boundary: Boundary<Tz>) -> (DateTime<Tz>, Duration) {
+        match boundary {
+            Boundary::At(time, dist) => (time, dist),
+            Boundary::After(change) => panic!("waits for a change at {:?}", change),
+        }
+    }
+
+    fn after<Tz: TimeZone>(boundary: Boundary<Tz>) -> DateTime<Tz> {
+        match boundary {
+            Boundary::At(time, _) => panic!("syncs to {:?}", time),
+            Boundary::After(change) => change,
+        }
+    }
+
+    #[test]
+    fn boundary_is_the_first_second_past_the_lead() {
+        let now = local(3600, (2026, 6, 1), (12, 0, 0, 400));
+        let (time, dist) = at(next_sync_time(now, Duration::milliseconds(500)));
+        assert_eq!(time, local(3600, (2026, 6, 1), (12, 0, 1, 0)));
+        assert_eq!(dist, Duration::milliseconds(600));
+
+        let (time, dist) = at(next_sync_time(now, Duration::milliseconds(700)));
+        assert_eq!(time, local(3600, (2026, 6, 1), (12, 0, 2, 0)));
+        assert_eq!(dist, Duration::milliseconds(1600));
+    }
+
+    #[test]
+    fn boundary_waits_out_midnight() {
+        let nepal = 5 * 3600 + 45 * 60;
+        let now = local(nepal, (2026, 12, 31), (23, 59, 59, 700));
+        let midnight = local(nepal, (2027, 1, 1), (0, 0, 0, 0));
+        assert_eq!(after(next_sync_time(now, Duration::zero())), midnight);
+        let now = local(nepal, (2026, 12, 31), (23, 59, 58, 700));
+        assert_eq!(after(next_sync_time(now, Duration::seconds(1))), midnight);
+
+        let (time, _) = at(next_sync_time(midnight, Duration::zero()));
+        assert_eq!(time, local(nepal, (2027, 1, 1), (0, 0, 1, 0)));
+    }
+
+    #[test]
+    fn boundary_waits_out_offset_changes() {
+        for rule in [Rule::SPRING, Rule::FALL] {
+            let change = rule.timestamp_opt(rule.change, 0).unwrap();
+            for before_ms in [100, 600, 1000] {
+                let now = change - Duration::milliseconds(before_ms);
+                let waited = after(next_sync_time(now, Duration::zero()));
+                assert_eq!(waited, change);
+                assert_eq!(waited.offset().fix().local_minus_utc(), rule.after);
+            }
+            let now = change - Duration::milliseconds(1400);
+            let (time, _) = at(next_sync_time(now, Duration::zero()));
+            assert_eq!(time, change - Duration::seconds(1));
+
+            let now = change + Duration::milliseconds(300);
+            let (time, dist) = at(next_sync_time(now, Duration::zero()));
+            assert_eq!(time, change + Duration::seconds(1));
+            assert_eq!(dist, Duration::milliseconds(700));
+        }
+    }
+
+    #[test]
+    fn truncation_handles_ambiguous_local_times() {
+        // 02:30 happens twice when summer time ends.
+        let first = Rule::FALL.timestamp_opt(Rule::FALL.change - 1800, 250_000_000);
+        let second = Rule::FALL.timestamp_opt(Rule::FALL.change + 1800, 250_000_000);
+        for time in [first.unwrap(), second.unwrap()] {
+            let truncated = time_trunc_second(&time);
+            assert_eq!(truncated, time - Duration::milliseconds(250));
+            assert_eq!(truncated.offset().fix(), time.offset().fix());
+        }
+    }
+}
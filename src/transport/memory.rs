@@ -0,0 +1,45 @@
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+
+use super::ClockTransport;
+
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub wall: DateTime<Local>,
+    pub data: Vec<u8>,
+}
+
+// Records everything sent instead of talking to a device. Clones share the
+// same log, so a handle kept by the caller sees what the sync wrote.
+#[derive(Clone, Default)]
+pub struct MemoryTransport {
+    records: Rc<RefCell<Vec<Record>>>,
+}
+
+impl MemoryTransport {
+    pub fn new() -> MemoryTransport {
+        MemoryTransport::default()
+    }
+
+    pub fn records(&self) -> Vec<Record> {
+        self.records.borrow().clone()
+    }
+}
+
+impl ClockTransport for MemoryTransport {
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.records.borrow_mut().push(Record {
+            wall: Local::now(),
+            data: data.to_vec(),
+        });
+        Ok(())
+    }
+
+    fn receive(&mut self, _: &mut [u8], _: Duration) -> io::Result<usize> {
+        Ok(0)
+    }
+}
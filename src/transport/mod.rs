@@ -0,0 +1,175 @@
+#[cfg(test)]
+mod memory;
+#[cfg(unix)]
+mod pty;
+mod rfc2217;
+mod serial;
+mod tcp;
+mod trace;
+
+use std::error::Error;
+use std::io;
+use std::time::Duration;
+
+use serialport::{DataBits, FlowControl, Parity, StopBits};
+
+#[cfg(test)]
+pub use memory::MemoryTransport;
+pub use trace::{read as read_trace, Direction, Trace};
+
+// Socket reads treat a zero timeout as "block forever".
+fn socket_timeout(timeout: Duration) -> Option<Duration> {
+    Some(timeout.max(Duration::from_micros(1)))
+}
+
+fn timed_out(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+pub const TCP_SCHEME: &str = "tcp://";
+pub const RFC2217_SCHEME: &str = "rfc2217://";
+pub const PTY_SCHEME: &str = "pty://";
+
+#[derive(Debug, Clone, Copy)]
+pub struct PortSettings {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    // Modem control levels set right after opening; None leaves them as the
+    // driver set them.
+    pub dtr: Option<bool>,
+    pub rts: Option<bool>,
+    // How long a USB serial adapter holds written bytes before they reach
+    // the UART.
+    pub usb_latency: Duration,
+}
+
+impl Default for PortSettings {
+    fn default() -> PortSettings {
+        PortSettings {
+            baud_rate: 115200,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            dtr: None,
+            rts: None,
+            usb_latency: Duration::ZERO,
+        }
+    }
+}
+
+impl PortSettings {
+    // Time the UART needs to shift out one character, start and stop bits
+    // included.
+    pub fn char_time(&self) -> Duration {
+        let data_bits = match self.data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        };
+        let parity_bits = match self.parity {
+            Parity::None => 0,
+            Parity::Odd | Parity::Even => 1,
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+        let bits: u64 = 1 + data_bits + parity_bits + stop_bits;
+        Duration::from_nanos(bits * 1_000_000_000 / u64::from(self.baud_rate.max(1)))
+    }
+
+    // From handing a byte to the adapter until its stop bit reaches the
+    // device, which is when the device acts on it.
+    pub fn line_latency(&self) -> Duration {
+        self.usb_latency + self.char_time()
+    }
+}
+
+pub trait ClockTransport {
+    fn send(&mut self, data: &[u8]) -> io::Result<()>;
+
+    // Reads whatever arrives within `timeout`; returns 0 if nothing did.
+    fn receive(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize>;
+
+    // How long a sent byte takes to reach the device.
+    fn latency(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    // How much later each further byte of a frame arrives; devices act on a
+    // frame only once its last byte is in.
+    fn char_time(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+// Reports a measured latency in place of the estimate from the line
+// settings.
+struct Calibrated {
+    inner: Box<dyn ClockTransport>,
+    latency: Duration,
+}
+
+impl ClockTransport for Calibrated {
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.inner.send(data)
+    }
+
+    fn receive(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        self.inner.receive(buf, timeout)
+    }
+
+    fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    fn char_time(&self) -> Duration {
+        self.inner.char_time()
+    }
+}
+
+pub fn with_latency(inner: Box<dyn ClockTransport>, latency: Duration) -> Box<dyn ClockTransport> {
+    Box::new(Calibrated { inner, latency })
+}
+
+// URL targets are opened directly instead of going through discovery.
+pub fn is_url(name: &str) -> bool {
+    name.contains("://")
+}
+
+pub fn open(
+    name: &str,
+    settings: &PortSettings,
+) -> Result<Box<dyn ClockTransport>, Box<dyn Error>> {
+    if let Some(addr) = name.strip_prefix(TCP_SCHEME) {
+        return Ok(Box::new(tcp::TcpTransport::connect(addr)?));
+    }
+    if let Some(addr) = name.strip_prefix(RFC2217_SCHEME) {
+        return Ok(Box::new(rfc2217::Rfc2217Port::connect(addr, settings)?));
+    }
+    if let Some(path) = name.strip_prefix(PTY_SCHEME) {
+        return open_pty(path);
+    }
+    if is_url(name) {
+        return Err(format!("Unsupported transport {}", name).into());
+    }
+    Ok(Box::new(serial::SerialTransport::open(name, settings)?))
+}
+
+#[cfg(unix)]
+fn open_pty(path: &str) -> Result<Box<dyn ClockTransport>, Box<dyn Error>> {
+    Ok(Box::new(pty::PtyTransport::open(path)?))
+}
+
+#[cfg(not(unix))]
+fn open_pty(_: &str) -> Result<Box<dyn ClockTransport>, Box<dyn Error>> {
+    Err("Pseudo-terminals are only supported on Unix".into())
+}
@@ -0,0 +1,60 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+use super::ClockTransport;
+
+// The slave end of a pseudo-terminal, e.g. one side of a `socat` pair with a
+// device simulator on the other.
+pub struct PtyTransport {
+    file: File,
+}
+
+impl PtyTransport {
+    pub fn open(path: &str) -> io::Result<PtyTransport> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(path)?;
+        // Raw mode, so no output processing rewrites the frame bytes, and
+        // exclusive so no other process can open the slave meanwhile.
+        unsafe {
+            let mut termios: libc::termios = mem::zeroed();
+            if libc::tcgetattr(file.as_raw_fd(), &mut termios) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(file.as_raw_fd(), libc::TCSANOW, &termios) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::ioctl(file.as_raw_fd(), libc::TIOCEXCL) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(PtyTransport { file })
+    }
+}
+
+impl ClockTransport for PtyTransport {
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data)
+    }
+
+    fn receive(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let mut pollfd = libc::pollfd {
+            fd: self.file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+            0 => Ok(0),
+            n if n < 0 => Err(io::Error::last_os_error()),
+            _ => self.file.read(buf),
+        }
+    }
+}
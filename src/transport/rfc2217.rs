@@ -0,0 +1,444 @@
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+use serialport::{DataBits, FlowControl, Parity, StopBits};
+
+use super::{tcp, ClockTransport, PortSettings};
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+const OPT_BINARY: u8 = 0;
+const OPT_SGA: u8 = 3;
+const OPT_COM_PORT: u8 = 44;
+
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const CONTROL_DTR_ON: u8 = 8;
+const CONTROL_DTR_OFF: u8 = 9;
+const CONTROL_RTS_ON: u8 = 11;
+const CONTROL_RTS_OFF: u8 = 12;
+// Server replies use the client command code plus this offset.
+const SERVER_OFFSET: u8 = 100;
+
+const NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum Event {
+    Data(u8),
+    Command(u8, u8),
+    Subnegotiation(u8, Vec<u8>),
+}
+
+#[derive(Default)]
+enum DecodeState {
+    #[default]
+    Data,
+    Iac,
+    Verb(u8),
+    Sb(Vec<u8>),
+    SbIac(Vec<u8>),
+}
+
+#[derive(Default)]
+struct Decoder {
+    state: DecodeState,
+}
+
+impl Decoder {
+    fn feed(&mut self, byte: u8) -> Option<Event> {
+        let (state, event) = match (std::mem::take(&mut self.state), byte) {
+            (DecodeState::Data, IAC) => (DecodeState::Iac, None),
+            (DecodeState::Data, b) => (DecodeState::Data, Some(Event::Data(b))),
+            (DecodeState::Iac, IAC) => (DecodeState::Data, Some(Event::Data(IAC))),
+            (DecodeState::Iac, SB) => (DecodeState::Sb(Vec::new()), None),
+            (DecodeState::Iac, verb @ (WILL | WONT | DO | DONT)) => (DecodeState::Verb(verb), None),
+            (DecodeState::Iac, _) => (DecodeState::Data, None),
+            (DecodeState::Verb(verb), option) => {
+                (DecodeState::Data, Some(Event::Command(verb, option)))
+            }
+            (DecodeState::Sb(buf), IAC) => (DecodeState::SbIac(buf), None),
+            (DecodeState::Sb(mut buf), b) => {
+                buf.push(b);
+                (DecodeState::Sb(buf), None)
+            }
+            (DecodeState::SbIac(mut buf), IAC) => {
+                buf.push(IAC);
+                (DecodeState::Sb(buf), None)
+            }
+            (DecodeState::SbIac(buf), SE) => match buf.split_first() {
+                Some((&option, data)) => (
+                    DecodeState::Data,
+                    Some(Event::Subnegotiation(option, data.to_vec())),
+                ),
+                None => (DecodeState::Data, None),
+            },
+            (DecodeState::SbIac(_), _) => (DecodeState::Data, None),
+        };
+        self.state = state;
+        event
+    }
+}
+
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &b in data {
+        escaped.push(b);
+        if b == IAC {
+            escaped.push(IAC);
+        }
+    }
+    escaped
+}
+
+fn com_port_command(command: u8, value: &[u8]) -> Vec<u8> {
+    let mut buf = vec![IAC, SB, OPT_COM_PORT, command];
+    buf.extend(escape(value));
+    buf.extend([IAC, SE]);
+    buf
+}
+
+pub struct Rfc2217Port {
+    stream: TcpStream,
+    decoder: Decoder,
+    rtt: Duration,
+    line_latency: Duration,
+    char_time: Duration,
+}
+
+impl Rfc2217Port {
+    pub fn connect(addr: &str, settings: &PortSettings) -> Result<Rfc2217Port, Box<dyn Error>> {
+        let (stream, rtt) = tcp::connect(addr)?;
+        Rfc2217Port::negotiate(stream, rtt, settings)
+    }
+
+    fn negotiate(
+        stream: TcpStream,
+        rtt: Duration,
+        settings: &PortSettings,
+    ) -> Result<Rfc2217Port, Box<dyn Error>> {
+        let mut port = Rfc2217Port {
+            stream,
+            decoder: Decoder::default(),
+            rtt,
+            line_latency: settings.line_latency(),
+            char_time: settings.char_time(),
+        };
+        let mut offer = Vec::new();
+        for option in [OPT_COM_PORT, OPT_BINARY, OPT_SGA] {
+            offer.extend([IAC, WILL, option, IAC, DO, option]);
+        }
+        port.stream.write_all(&offer)?;
+
+        let data_size = match settings.data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        };
+        let parity = match settings.parity {
+            Parity::None => 1,
+            Parity::Odd => 2,
+            Parity::Even => 3,
+        };
+        let stop_size = match settings.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+        let control = match settings.flow_control {
+            FlowControl::None => 1,
+            FlowControl::Software => 2,
+            FlowControl::Hardware => 3,
+        };
+        let mut expected: Vec<(u8, Vec<u8>)> = vec![
+            (SET_BAUDRATE, settings.baud_rate.to_be_bytes().to_vec()),
+            (SET_DATASIZE, vec![data_size]),
+            (SET_PARITY, vec![parity]),
+            (SET_STOPSIZE, vec![stop_size]),
+            (SET_CONTROL, vec![control]),
+        ];
+        let mut lines = |level: Option<bool>, on: u8, off: u8| {
+            if let Some(level) = level {
+                expected.push((SET_CONTROL, vec![if level { on } else { off }]));
+            }
+        };
+        lines(settings.dtr, CONTROL_DTR_ON, CONTROL_DTR_OFF);
+        lines(settings.rts, CONTROL_RTS_ON, CONTROL_RTS_OFF);
+        let mut request = Vec::new();
+        for (command, value) in &expected {
+            request.extend(com_port_command(*command, value));
+        }
+
+        let deadline = Instant::now() + NEGOTIATION_TIMEOUT;
+        let mut accepted = false;
+        let mut buf = [0u8; 256];
+        while !expected.is_empty() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err("RFC 2217 server did not confirm the port settings".into());
+            }
+            port.stream.set_read_timeout(Some(remaining))?;
+            let len = match port.stream.read(&mut buf) {
+                Ok(0) => return Err("RFC 2217 server closed the connection".into()),
+                Ok(len) => len,
+                Err(e) if super::timed_out(&e) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for &b in &buf[..len] {
+                match port.decoder.feed(b) {
+                    Some(Event::Command(DO, OPT_COM_PORT)) if !accepted => {
+                        accepted = true;
+                        port.stream.write_all(&request)?;
+                    }
+                    Some(Event::Command(DONT, OPT_COM_PORT)) => {
+                        return Err("Server refused the RFC 2217 COM port option".into());
+                    }
+                    Some(Event::Subnegotiation(OPT_COM_PORT, data)) => {
+                        if let Some((&reply, value)) = data.split_first() {
+                            let command = reply.wrapping_sub(SERVER_OFFSET);
+                            if let Some(i) = expected.iter().position(|(c, _)| *c == command) {
+                                let (_, requested) = expected.remove(i);
+                                if value != requested.as_slice() {
+                                    warn!(
+                                        "RFC 2217 server answered command {} with {:02x?}, requested {:02x?}",
+                                        command, value, requested
+                                    );
+                                }
+                            }
+                        }
+                    }
+                    Some(event) => port.answer(event)?,
+                    None => {}
+                }
+            }
+        }
+        port.stream.set_read_timeout(None)?;
+        debug!("RFC 2217 port settings confirmed: {:?}", settings);
+        Ok(port)
+    }
+
+    // Refuses every option besides the ones offered in `negotiate`.
+    fn answer(&mut self, event: Event) -> io::Result<()> {
+        let supported = |option| matches!(option, OPT_BINARY | OPT_SGA | OPT_COM_PORT);
+        match event {
+            Event::Command(DO, option) if !supported(option) => {
+                self.stream.write_all(&[IAC, WONT, option])
+            }
+            Event::Command(WILL, option) if !supported(option) => {
+                self.stream.write_all(&[IAC, DONT, option])
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Write for Rfc2217Port {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write_all(&escape(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl ClockTransport for Rfc2217Port {
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write_all(data)
+    }
+
+    fn receive(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        self.stream
+            .set_read_timeout(super::socket_timeout(timeout))?;
+        match self.read(buf) {
+            Err(e) if super::timed_out(&e) => Ok(0),
+            result => result,
+        }
+    }
+
+    // Network delay to the server plus the server's adapter and UART.
+    fn latency(&self) -> Duration {
+        self.rtt / 2 + self.line_latency
+    }
+
+    fn char_time(&self) -> Duration {
+        self.char_time
+    }
+}
+
+impl Read for Rfc2217Port {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut raw = vec![0u8; buf.len()];
+        loop {
+            let len = self.stream.read(&mut raw)?;
+            if len == 0 {
+                return Ok(0);
+            }
+            let mut filled = 0;
+            for &b in &raw[..len] {
+                match self.decoder.feed(b) {
+                    Some(Event::Data(b)) => {
+                        buf[filled] = b;
+                        filled += 1;
+                    }
+                    Some(event) => self.answer(event)?,
+                    None => {}
+                }
+            }
+            if filled > 0 {
+                return Ok(filled);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    // Confirms every COM port setting as requested and records them, then
+    // reads `data_len` raw bytes and answers with `reply`.
+    fn stand_in(
+        listener: TcpListener,
+        settings: usize,
+        data_len: usize,
+        reply: Vec<u8>,
+    ) -> (Vec<(u8, Vec<u8>)>, Vec<u8>) {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(&[IAC, DO, OPT_COM_PORT]).unwrap();
+        let mut decoder = Decoder::default();
+        let mut received = Vec::new();
+        let mut byte = [0u8];
+        while received.len() < settings {
+            stream.read_exact(&mut byte).unwrap();
+            if let Some(Event::Subnegotiation(OPT_COM_PORT, data)) = decoder.feed(byte[0]) {
+                let (&command, value) = data.split_first().unwrap();
+                stream
+                    .write_all(&com_port_command(command + SERVER_OFFSET, value))
+                    .unwrap();
+                received.push((command, value.to_vec()));
+            }
+        }
+        let mut data = vec![0u8; data_len];
+        stream.read_exact(&mut data).unwrap();
+        stream.write_all(&reply).unwrap();
+        (received, data)
+    }
+
+    #[test]
+    fn negotiates_settings_and_escapes_data() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let reply = vec![
+            0x06,
+            IAC,
+            IAC,
+            IAC,
+            SB,
+            OPT_COM_PORT,
+            SET_PARITY + SERVER_OFFSET,
+            3,
+            IAC,
+            SE,
+            0x15,
+        ];
+        let server = thread::spawn(move || stand_in(listener, 7, 6, reply));
+
+        let settings = PortSettings {
+            baud_rate: 9600,
+            data_bits: DataBits::Seven,
+            parity: Parity::Even,
+            stop_bits: StopBits::Two,
+            flow_control: FlowControl::Hardware,
+            dtr: Some(true),
+            rts: Some(false),
+            ..PortSettings::default()
+        };
+        let mut port = Rfc2217Port::connect(&addr.to_string(), &settings).unwrap();
+        assert!(port.latency() > Duration::ZERO);
+        port.send(&[0x53, IAC, 0x01, IAC]).unwrap();
+
+        let mut data = Vec::new();
+        let mut buf = [0u8; 16];
+        while data.len() < 3 {
+            let len = port.read(&mut buf).unwrap();
+            assert!(len > 0, "connection closed after {:02x?}", data);
+            data.extend_from_slice(&buf[..len]);
+        }
+        assert_eq!(data, [0x06, IAC, 0x15]);
+
+        let (negotiated, raw) = server.join().unwrap();
+        assert_eq!(
+            negotiated,
+            [
+                (SET_BAUDRATE, 9600u32.to_be_bytes().to_vec()),
+                (SET_DATASIZE, vec![7]),
+                (SET_PARITY, vec![3]),
+                (SET_STOPSIZE, vec![2]),
+                (SET_CONTROL, vec![3]),
+                (SET_CONTROL, vec![CONTROL_DTR_ON]),
+                (SET_CONTROL, vec![CONTROL_RTS_OFF]),
+            ]
+        );
+        assert_eq!(raw, [0x53, IAC, IAC, 0x01, IAC, IAC]);
+    }
+
+    #[test]
+    fn decodes_escapes_and_subnegotiations() {
+        let mut decoder = Decoder::default();
+        let input = [
+            b'a',
+            IAC,
+            IAC,
+            IAC,
+            SB,
+            OPT_COM_PORT,
+            SET_BAUDRATE + SERVER_OFFSET,
+            0,
+            0,
+            IAC,
+            IAC,
+            0,
+            IAC,
+            SE,
+            IAC,
+            WILL,
+            OPT_SGA,
+            b'b',
+        ];
+        let mut data = Vec::new();
+        let mut subnegotiations = Vec::new();
+        let mut commands = Vec::new();
+        for b in input {
+            match decoder.feed(b) {
+                Some(Event::Data(b)) => data.push(b),
+                Some(Event::Subnegotiation(option, value)) => subnegotiations.push((option, value)),
+                Some(Event::Command(verb, option)) => commands.push((verb, option)),
+                None => {}
+            }
+        }
+        assert_eq!(data, [b'a', IAC, b'b']);
+        assert_eq!(
+            subnegotiations,
+            [(
+                OPT_COM_PORT,
+                vec![SET_BAUDRATE + SERVER_OFFSET, 0, 0, IAC, 0]
+            )]
+        );
+        assert_eq!(commands, [(WILL, OPT_SGA)]);
+    }
+}
@@ -0,0 +1,67 @@
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use serialport::SerialPort;
+
+use super::{ClockTransport, PortSettings};
+
+pub struct SerialTransport {
+    port: Box<dyn SerialPort>,
+    line_latency: Duration,
+    char_time: Duration,
+}
+
+impl SerialTransport {
+    pub fn open(name: &str, settings: &PortSettings) -> serialport::Result<SerialTransport> {
+        let builder = serialport::new(name, settings.baud_rate)
+            .data_bits(settings.data_bits)
+            .parity(settings.parity)
+            .stop_bits(settings.stop_bits)
+            .flow_control(settings.flow_control);
+        // Windows opens COM ports exclusively already; on Unix it takes
+        // TIOCEXCL.
+        #[cfg(unix)]
+        let mut port: Box<dyn SerialPort> = {
+            let mut port = builder.open_native()?;
+            port.set_exclusive(true)?;
+            Box::new(port)
+        };
+        #[cfg(not(unix))]
+        let mut port = builder.open()?;
+        // Arduino-style boards reset on a DTR edge, so the lines are set
+        // before anything is sent.
+        if let Some(dtr) = settings.dtr {
+            port.write_data_terminal_ready(dtr)?;
+        }
+        if let Some(rts) = settings.rts {
+            port.write_request_to_send(rts)?;
+        }
+        Ok(SerialTransport {
+            port,
+            line_latency: settings.line_latency(),
+            char_time: settings.char_time(),
+        })
+    }
+}
+
+impl ClockTransport for SerialTransport {
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.port.write_all(data)
+    }
+
+    fn receive(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        self.port.set_timeout(timeout)?;
+        match self.port.read(buf) {
+            Err(e) if super::timed_out(&e) => Ok(0),
+            result => result,
+        }
+    }
+
+    fn latency(&self) -> Duration {
+        self.line_latency
+    }
+
+    fn char_time(&self) -> Duration {
+        self.char_time
+    }
+}
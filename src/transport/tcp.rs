@@ -0,0 +1,114 @@
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+
+use super::ClockTransport;
+
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct TcpTransport {
+    stream: TcpStream,
+    rtt: Duration,
+}
+
+// The TCP handshake takes one round trip, which is the best estimate of the
+// network delay to the device server available before sending anything.
+pub fn connect(addr: &str) -> Result<(TcpStream, Duration), Box<dyn Error>> {
+    let mut last_error: Box<dyn Error> = format!("{} did not resolve to any address", addr).into();
+    for socket_addr in addr.to_socket_addrs()? {
+        let start = Instant::now();
+        match TcpStream::connect_timeout(&socket_addr, TCP_CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                let rtt = start.elapsed();
+                stream.set_nodelay(true)?;
+                info!(
+                    "Connected to {} ({}), round trip {:?}",
+                    addr, socket_addr, rtt
+                );
+                return Ok((stream, rtt));
+            }
+            Err(e) => {
+                debug!("Failed to connect to {}: {}", socket_addr, e);
+                last_error = e.into();
+            }
+        }
+    }
+    Err(last_error)
+}
+
+impl TcpTransport {
+    pub fn connect(addr: &str) -> Result<TcpTransport, Box<dyn Error>> {
+        let (stream, rtt) = connect(addr)?;
+        Ok(TcpTransport { stream, rtt })
+    }
+}
+
+impl ClockTransport for TcpTransport {
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.stream.write_all(data)
+    }
+
+    fn receive(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        self.stream
+            .set_read_timeout(super::socket_timeout(timeout))?;
+        match self.stream.read(buf) {
+            Err(e) if super::timed_out(&e) => Ok(0),
+            result => result,
+        }
+    }
+
+    fn latency(&self) -> Duration {
+        self.rtt / 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::protocol::{Frame, ACK};
+    use crate::transport::{self, PortSettings};
+
+    #[test]
+    fn exchanges_frames_with_a_local_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let frame = Frame::SetTimeOfDay { seconds: 3723 }.encode();
+        let len = frame.len();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![0u8; len];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(&[ACK]).unwrap();
+            request
+        });
+
+        let started = Instant::now();
+        let mut transport =
+            transport::open(&format!("tcp://{}", addr), &PortSettings::default()).unwrap();
+        let opened = started.elapsed();
+        let mut buf = [0u8; 8];
+        assert_eq!(
+            transport
+                .receive(&mut buf, Duration::from_millis(10))
+                .unwrap(),
+            0
+        );
+
+        transport.send(&frame).unwrap();
+        let len = transport.receive(&mut buf, Duration::from_secs(1)).unwrap();
+        assert_eq!(&buf[..len], &[ACK]);
+        assert_eq!(server.join().unwrap(), frame);
+
+        // Half the connect round trip, which fits in the time open() took.
+        let latency = transport.latency();
+        assert!(latency > Duration::ZERO);
+        assert!(latency * 2 <= opened);
+    }
+}
@@ -0,0 +1,202 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, TimeZone};
+use log::warn;
+
+use super::ClockTransport;
+
+// Traces are pcap files with nanosecond timestamps and the first user link
+// type, so they also open in Wireshark. Each packet starts with a
+// pseudo-header: direction, monotonic nanoseconds since the trace was
+// created, and the length-prefixed port name.
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const LINKTYPE_USER0: u32 = 147;
+const SNAPLEN: u32 = 65535;
+const FILE_HEADER_LEN: usize = 24;
+const RECORD_HEADER_LEN: usize = 16;
+const PSEUDO_HEADER_LEN: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+    // The sync picked a boundary; the wall time is the boundary itself.
+    Boundary,
+}
+
+impl Direction {
+    fn code(self) -> u8 {
+        match self {
+            Direction::Sent => 0,
+            Direction::Received => 1,
+            Direction::Boundary => 2,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Direction> {
+        match code {
+            0 => Some(Direction::Sent),
+            1 => Some(Direction::Received),
+            2 => Some(Direction::Boundary),
+            _ => None,
+        }
+    }
+}
+
+pub struct Event {
+    pub wall: DateTime<Local>,
+    pub monotonic: Duration,
+    pub direction: Direction,
+    pub port: String,
+    pub data: Vec<u8>,
+}
+
+struct Writer {
+    file: File,
+    started: Instant,
+}
+
+// Clones append to the same file.
+#[derive(Clone)]
+pub struct Trace {
+    writer: Rc<RefCell<Writer>>,
+}
+
+impl Trace {
+    pub fn create(path: &Path) -> Result<Trace, Box<dyn Error>> {
+        let mut file = File::create(path)
+            .map_err(|e| format!("Failed to create trace {}: {}", path.display(), e))?;
+        let mut header = Vec::with_capacity(FILE_HEADER_LEN);
+        header.extend(PCAP_MAGIC_NANOS.to_le_bytes());
+        header.extend(2u16.to_le_bytes());
+        header.extend(4u16.to_le_bytes());
+        header.extend(0i32.to_le_bytes());
+        header.extend(0u32.to_le_bytes());
+        header.extend(SNAPLEN.to_le_bytes());
+        header.extend(LINKTYPE_USER0.to_le_bytes());
+        file.write_all(&header)?;
+        Ok(Trace {
+            writer: Rc::new(RefCell::new(Writer {
+                file,
+                started: Instant::now(),
+            })),
+        })
+    }
+
+    fn record(&self, direction: Direction, port: &str, data: &[u8], wall: DateTime<Local>) {
+        let mut writer = self.writer.borrow_mut();
+        let monotonic = writer.started.elapsed().as_nanos() as u64;
+        let port = &port.as_bytes()[..port.len().min(usize::from(u8::MAX))];
+        let len = (PSEUDO_HEADER_LEN + port.len() + data.len()) as u32;
+        let mut buf = Vec::with_capacity(RECORD_HEADER_LEN + len as usize);
+        buf.extend((wall.timestamp() as u32).to_le_bytes());
+        buf.extend(wall.timestamp_subsec_nanos().to_le_bytes());
+        buf.extend(len.to_le_bytes());
+        buf.extend(len.to_le_bytes());
+        buf.push(direction.code());
+        buf.extend(monotonic.to_be_bytes());
+        buf.push(port.len() as u8);
+        buf.extend(port);
+        buf.extend(data);
+        if let Err(e) = writer.file.write_all(&buf) {
+            warn!("Failed to write trace: {}", e);
+        }
+    }
+
+    pub fn boundary(&self, time: DateTime<Local>) {
+        self.record(Direction::Boundary, "", &[], time);
+    }
+
+    pub fn wrap(&self, port: &str, inner: Box<dyn ClockTransport>) -> Box<dyn ClockTransport> {
+        Box::new(TracedTransport {
+            inner,
+            port: port.to_string(),
+            trace: self.clone(),
+        })
+    }
+}
+
+struct TracedTransport {
+    inner: Box<dyn ClockTransport>,
+    port: String,
+    trace: Trace,
+}
+
+impl ClockTransport for TracedTransport {
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        let wall = Local::now();
+        self.inner.send(data)?;
+        self.trace.record(Direction::Sent, &self.port, data, wall);
+        Ok(())
+    }
+
+    fn receive(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let len = self.inner.receive(buf, timeout)?;
+        if len > 0 {
+            self.trace
+                .record(Direction::Received, &self.port, &buf[..len], Local::now());
+        }
+        Ok(len)
+    }
+
+    fn latency(&self) -> Duration {
+        self.inner.latency()
+    }
+
+    fn char_time(&self) -> Duration {
+        self.inner.char_time()
+    }
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn read(path: &Path) -> Result<Vec<Event>, Box<dyn Error>> {
+    let buf =
+        fs::read(path).map_err(|e| format!("Failed to read trace {}: {}", path.display(), e))?;
+    if buf.len() < FILE_HEADER_LEN
+        || u32_at(&buf, 0) != PCAP_MAGIC_NANOS
+        || u32_at(&buf, 20) != LINKTYPE_USER0
+    {
+        return Err(format!("{} is not a trace written by this tool", path.display()).into());
+    }
+    let mut events = Vec::new();
+    let mut offset = FILE_HEADER_LEN;
+    while offset < buf.len() {
+        let truncated = || format!("Trace truncated at offset {}", offset);
+        let header = buf
+            .get(offset..offset + RECORD_HEADER_LEN)
+            .ok_or_else(truncated)?;
+        let len = u32_at(header, 8) as usize;
+        let packet = buf
+            .get(offset + RECORD_HEADER_LEN..offset + RECORD_HEADER_LEN + len)
+            .ok_or_else(truncated)?;
+        let port_len = usize::from(*packet.get(PSEUDO_HEADER_LEN - 1).ok_or_else(truncated)?);
+        let data_start = PSEUDO_HEADER_LEN + port_len;
+        if data_start > packet.len() {
+            return Err(truncated().into());
+        }
+        let direction = Direction::from_code(packet[0])
+            .ok_or_else(|| format!("Unknown direction {} at offset {}", packet[0], offset))?;
+        let wall = Local
+            .timestamp_opt(i64::from(u32_at(header, 0)), u32_at(header, 4))
+            .single()
+            .ok_or_else(|| format!("Invalid timestamp at offset {}", offset))?;
+        events.push(Event {
+            wall,
+            monotonic: Duration::from_nanos(u64::from_be_bytes(packet[1..9].try_into()?)),
+            direction,
+            port: String::from_utf8_lossy(&packet[PSEUDO_HEADER_LEN..data_start]).into_owned(),
+            data: packet[data_start..].to_vec(),
+        });
+        offset += RECORD_HEADER_LEN + len;
+    }
+    Ok(events)
+}
@@ -0,0 +1,126 @@
+use std::error::Error;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+
+// How long the hybrid strategy spins after sleeping.
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+// Deadlines are on the monotonic clock, so a step of the system clock moves
+// none of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum WaitStrategy {
+    /// thread::sleep for the remaining time; may overshoot by a millisecond
+    /// or more
+    #[default]
+    Sleep,
+    /// clock_nanosleep on CLOCK_MONOTONIC with an absolute deadline, so time
+    /// spent before the call does not add up
+    Nanosleep,
+    /// A CLOCK_MONOTONIC timerfd armed with the absolute deadline
+    Timerfd,
+    /// Sleep until shortly before the deadline, then spin
+    Hybrid,
+}
+
+pub fn wait_until(strategy: WaitStrategy, deadline: Instant) -> Result<(), Box<dyn Error>> {
+    match strategy {
+        WaitStrategy::Sleep => sleep_until(deadline),
+        WaitStrategy::Nanosleep => nanosleep_until(deadline),
+        WaitStrategy::Timerfd => timerfd_until(deadline),
+        WaitStrategy::Hybrid => {
+            sleep_until(deadline.checked_sub(SPIN_MARGIN).unwrap_or(deadline))?;
+            while Instant::now() < deadline {
+                std::hint::spin_loop();
+            }
+            Ok(())
+        }
+    }
+}
+
+fn sleep_until(deadline: Instant) -> Result<(), Box<dyn Error>> {
+    thread::sleep(deadline.saturating_duration_since(Instant::now()));
+    Ok(())
+}
+
+// `deadline` as an absolute CLOCK_MONOTONIC time.
+#[cfg(target_os = "linux")]
+fn timespec(deadline: Instant) -> Result<libc::timespec, Box<dyn Error>> {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let nanos = now.tv_nsec as u64 + u64::from(remaining.subsec_nanos());
+    Ok(libc::timespec {
+        tv_sec: now.tv_sec + (remaining.as_secs() + nanos / 1_000_000_000) as libc::time_t,
+        tv_nsec: (nanos % 1_000_000_000) as libc::c_long,
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn nanosleep_until(deadline: Instant) -> Result<(), Box<dyn Error>> {
+    let request = timespec(deadline)?;
+    loop {
+        let result = unsafe {
+            libc::clock_nanosleep(
+                libc::CLOCK_MONOTONIC,
+                libc::TIMER_ABSTIME,
+                &request,
+                std::ptr::null_mut(),
+            )
+        };
+        match result {
+            0 => return Ok(()),
+            libc::EINTR => continue,
+            errno => return Err(std::io::Error::from_raw_os_error(errno).into()),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn timerfd_until(deadline: Instant) -> Result<(), Box<dyn Error>> {
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::os::unix::io::FromRawFd;
+
+    let fd = unsafe { libc::timerfd_create(libc::CLOCK_MONOTONIC, libc::TFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    // Owns the descriptor from here on, so it is closed on every path.
+    let mut timer = unsafe { File::from_raw_fd(fd) };
+    let spec = libc::itimerspec {
+        it_interval: libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        },
+        it_value: timespec(deadline)?,
+    };
+    if unsafe { libc::timerfd_settime(fd, libc::TFD_TIMER_ABSTIME, &spec, std::ptr::null_mut()) }
+        < 0
+    {
+        return Err(io::Error::last_os_error().into());
+    }
+    let mut expirations = [0u8; 8];
+    loop {
+        match timer.read_exact(&mut expirations) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            result => return Ok(result?),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn nanosleep_until(_: Instant) -> Result<(), Box<dyn Error>> {
+    Err("clock_nanosleep waits are only supported on Linux".into())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn timerfd_until(_: Instant) -> Result<(), Box<dyn Error>> {
+    Err("timerfd waits are only supported on Linux".into())
+}